@@ -0,0 +1,258 @@
+use std::sync::mpsc::{SyncSender, TrySendError};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{
+    Device, FromSample, SampleFormat, SampleRate, SizedSample, Stream, SupportedStreamConfig,
+    SupportedStreamConfigRange,
+};
+
+use crate::audio_source::{MonoResampler, OUTPUT_SAMPLE_RATE};
+use crate::error::{Error, Result};
+
+// Sample formats we can record from, in order of preference
+const SAMPLE_FORMATS: &[SampleFormat] = &[SampleFormat::F32, SampleFormat::I16, SampleFormat::U16];
+
+/// Names of the input devices of the default host.
+pub fn list_input_devices() -> Result<Vec<String>> {
+    let host = cpal::default_host();
+    let devices = host
+        .input_devices()
+        .map_err(|err| Error::AudioInput(err.to_string()))?;
+    Ok(devices.filter_map(|device| device.name().ok()).collect())
+}
+
+/// Open a paused input stream that sends 16 kHz mono chunks to `tx`.
+///
+/// `device_name` picks the first input device whose name contains it,
+/// ignoring case. Otherwise the default input device is used. If a stream
+/// config fails to build, the next supported one is tried.
+pub fn create_paused_audio_stream(
+    device_name: Option<&str>,
+    tx: SyncSender<Vec<f32>>,
+) -> Result<Stream> {
+    let input_device = find_device(device_name)?;
+    println!("Input device: {:?}", input_device.name());
+
+    let mut last_error = Error::AudioInput("no supported input config found".into());
+    for input_config in candidate_configs(&input_device) {
+        match build_stream(&input_device, &input_config, tx.clone()) {
+            Ok(stream) => {
+                println!("Input config: {:?}", input_config);
+                return Ok(stream);
+            }
+            Err(err) => {
+                eprintln!("Input config {:?} failed: {}", input_config, err);
+                last_error = err;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+fn find_device(device_name: Option<&str>) -> Result<Device> {
+    let host = cpal::default_host();
+    let Some(device_name) = device_name else {
+        return host
+            .default_input_device()
+            .ok_or_else(|| Error::AudioInput("no input device found".into()));
+    };
+
+    let wanted = device_name.to_lowercase();
+    host.input_devices()
+        .map_err(|err| Error::AudioInput(err.to_string()))?
+        .find(|device| {
+            device
+                .name()
+                .map(|name| name.to_lowercase().contains(&wanted))
+                .unwrap_or(false)
+        })
+        .ok_or_else(|| {
+            Error::AudioInput(format!(
+                "no input device matching {:?}, see `jarvy devices`",
+                device_name
+            ))
+        })
+}
+
+/// The default config if we can record from it, then every other supported one.
+fn candidate_configs(device: &Device) -> Vec<SupportedStreamConfig> {
+    let mut candidates = vec![];
+    match device.default_input_config() {
+        Ok(config) if SAMPLE_FORMATS.contains(&config.sample_format()) => candidates.push(config),
+        Ok(config) => eprintln!("Default input config {:?} is not supported", config),
+        Err(err) => eprintln!("No default input config: {}", err),
+    }
+
+    let ranges = device
+        .supported_input_configs()
+        .map(|ranges| ranges.collect::<Vec<_>>())
+        .unwrap_or_default();
+    for config in rank_configs(ranges) {
+        if !candidates.contains(&config) {
+            candidates.push(config);
+        }
+    }
+    candidates
+}
+
+/// Order supported ranges by sample format, preferring 16 kHz to avoid resampling.
+fn rank_configs(mut ranges: Vec<SupportedStreamConfigRange>) -> Vec<SupportedStreamConfig> {
+    ranges.retain(|range| SAMPLE_FORMATS.contains(&range.sample_format()));
+    ranges.sort_by_key(|range| {
+        SAMPLE_FORMATS
+            .iter()
+            .position(|&format| format == range.sample_format())
+    });
+
+    let target = SampleRate(OUTPUT_SAMPLE_RATE as u32);
+    ranges
+        .into_iter()
+        .map(|range| {
+            if range.min_sample_rate() <= target && target <= range.max_sample_rate() {
+                range.with_sample_rate(target)
+            } else {
+                range.with_max_sample_rate()
+            }
+        })
+        .collect()
+}
+
+fn build_stream(
+    device: &Device,
+    config: &SupportedStreamConfig,
+    tx: SyncSender<Vec<f32>>,
+) -> Result<Stream> {
+    // Create resampler to convert the audio from the input device's sample rate to 16 kHz mono
+    let resampler =
+        MonoResampler::new(config.sample_rate().0 as usize, config.channels() as usize)?;
+
+    let stream = match config.sample_format() {
+        SampleFormat::F32 => build_typed_stream::<f32>(device, config, tx, resampler),
+        SampleFormat::I16 => build_typed_stream::<i16>(device, config, tx, resampler),
+        SampleFormat::U16 => build_typed_stream::<u16>(device, config, tx, resampler),
+        format => Err(Error::AudioInput(format!(
+            "unsupported sample format {}",
+            format
+        ))),
+    }?;
+
+    // Initialise with a paused stream
+    stream
+        .pause()
+        .map_err(|err| Error::AudioInput(err.to_string()))?;
+
+    Ok(stream)
+}
+
+fn build_typed_stream<T>(
+    device: &Device,
+    config: &SupportedStreamConfig,
+    tx: SyncSender<Vec<f32>>,
+    mut resampler: MonoResampler,
+) -> Result<Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let mut samples = vec![];
+    device
+        .build_input_stream(
+            &config.config(),
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                samples.clear();
+                samples.extend(data.iter().map(|&sample| sample.to_sample::<f32>()));
+                audio_input_stream_data_callback(&samples, &tx, &mut resampler);
+            },
+            move |err| eprintln!("An error occurred on the input audio stream: {}", err),
+            None,
+        )
+        .map_err(|err| Error::AudioInput(err.to_string()))
+}
+
+fn audio_input_stream_data_callback(
+    raw_samples: &[f32],
+    tx: &SyncSender<Vec<f32>>,
+    resampler: &mut MonoResampler,
+) {
+    // Convert to mono and resample the audio to get the target sample rate
+    let mono_samples = match resampler.process(raw_samples) {
+        Ok(mono_samples) => mono_samples,
+        Err(err) => {
+            eprintln!("Dropping audio: {}", err);
+            return;
+        }
+    };
+    if mono_samples.is_empty() {
+        return;
+    }
+
+    // Send the audio to the main thread without ever blocking the audio thread.
+    // If the receiver is gone the session is shutting down, so stop sending.
+    if let Err(TrySendError::Full(_)) = tx.try_send(mono_samples) {
+        eprintln!("Dropping audio: recording is not keeping up");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::{Sample, SupportedBufferSize};
+
+    fn range(
+        channels: u16,
+        min: u32,
+        max: u32,
+        format: SampleFormat,
+    ) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            SampleRate(min),
+            SampleRate(max),
+            SupportedBufferSize::Unknown,
+            format,
+        )
+    }
+
+    #[test]
+    fn test_rank_configs() {
+        let ranges = vec![
+            range(2, 44_100, 48_000, SampleFormat::I16),
+            range(6, 8_000, 96_000, SampleFormat::U16),
+            range(1, 8_000, 48_000, SampleFormat::I32),
+            range(4, 48_000, 48_000, SampleFormat::F32),
+        ];
+
+        let ranked = rank_configs(ranges)
+            .iter()
+            .map(|config| {
+                (
+                    config.sample_format(),
+                    config.channels(),
+                    config.sample_rate().0,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ranked,
+            vec![
+                (SampleFormat::F32, 4, 48_000),
+                (SampleFormat::I16, 2, 48_000),
+                (SampleFormat::U16, 6, 16_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_integer_samples_are_downmixed() {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        let mut resampler = MonoResampler::new(OUTPUT_SAMPLE_RATE, 3).unwrap();
+
+        // Three channels of u16 centred on 32768
+        let frame = [u16::MAX, 32_768, 0].map(|sample| sample.to_sample::<f32>());
+        audio_input_stream_data_callback(&frame, &tx, &mut resampler);
+
+        let mono = rx.recv().unwrap();
+        assert_eq!(mono.len(), 1);
+        assert!(mono[0].abs() < 1e-3);
+    }
+}
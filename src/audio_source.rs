@@ -0,0 +1,365 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use clap::ValueEnum;
+use rubato::{InterpolationParameters, InterpolationType, Resampler, SincFixedIn, WindowFunction};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::error::{Error, Result};
+use crate::traits::AudioSource;
+
+pub const OUTPUT_SAMPLE_RATE: usize = 16_000; // as required by Whisper
+const AUDIO_BUFFER: usize = 512;
+const PCM_CHUNK_FRAMES: usize = 4096;
+
+/// Downmix interleaved audio and resample it to 16 kHz mono.
+///
+/// Input of any length is buffered into the fixed-size chunks the resampler needs.
+pub struct MonoResampler {
+    sample_rate: usize,
+    channels: usize,
+    // `None` if the input is already at 16 kHz
+    resampler: Option<SincFixedIn<f32>>,
+    pending: Vec<f32>,
+    // Mono frames taken in and samples put out, to know where the output ends
+    frames_in: usize,
+    frames_out: usize,
+}
+
+impl MonoResampler {
+    pub fn new(sample_rate: usize, channels: usize) -> Result<Self> {
+        let resampler = if sample_rate == OUTPUT_SAMPLE_RATE {
+            None
+        } else {
+            Some(create_resampler(sample_rate)?)
+        };
+        Ok(Self {
+            sample_rate,
+            channels: channels.max(1),
+            resampler,
+            pending: Vec::new(),
+            frames_in: 0,
+            frames_out: 0,
+        })
+    }
+
+    /// Convert the next piece of interleaved audio. May return nothing until a full chunk is in.
+    pub fn process(&mut self, interleaved: &[f32]) -> Result<Vec<f32>> {
+        // Average the channels of each frame
+        let pending = self.pending.len();
+        self.pending.extend(
+            interleaved
+                .chunks(self.channels)
+                .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32),
+        );
+        self.frames_in += self.pending.len() - pending;
+
+        let Some(resampler) = &mut self.resampler else {
+            return Ok(std::mem::take(&mut self.pending));
+        };
+
+        let mut output = Vec::new();
+        let mut consumed = 0;
+        while self.pending.len() - consumed >= AUDIO_BUFFER {
+            let chunk = &self.pending[consumed..consumed + AUDIO_BUFFER];
+            let resampled = resampler
+                .process(&[chunk], None)
+                .map_err(|err| Error::Resample(err.to_string()))?;
+            output.extend_from_slice(&resampled[0]);
+            consumed += AUDIO_BUFFER;
+        }
+        self.pending.drain(..consumed);
+        self.frames_out += output.len();
+        Ok(output)
+    }
+
+    /// Convert whatever is still buffered, padding the last chunk with silence.
+    pub fn finish(&mut self) -> Result<Vec<f32>> {
+        if self.resampler.is_none() {
+            return Ok(std::mem::take(&mut self.pending));
+        }
+        // The resampler holds back the end of its input until it sees what
+        // follows, so push silence through until all of it is out
+        let expected = self.frames_in * OUTPUT_SAMPLE_RATE / self.sample_rate;
+        let mut output = Vec::new();
+        while self.frames_out < expected {
+            self.pending.resize(AUDIO_BUFFER, 0.0);
+            output.extend(self.process(&[])?);
+        }
+        // Drop the resampled padding
+        let padding = self.frames_out - expected;
+        output.truncate(output.len().saturating_sub(padding));
+        self.pending.clear();
+        self.frames_out = expected;
+        Ok(output)
+    }
+}
+
+/// Create a resampler that converts `sample_rate` to 16 kHz.
+fn create_resampler(sample_rate: usize) -> Result<SincFixedIn<f32>> {
+    SincFixedIn::<f32>::new(
+        OUTPUT_SAMPLE_RATE as f64 / sample_rate as f64,
+        2.0,
+        InterpolationParameters {
+            sinc_len: 128,
+            f_cutoff: 0.95,
+            interpolation: InterpolationType::Linear,
+            oversampling_factor: 128,
+            window: WindowFunction::BlackmanHarris2,
+        },
+        AUDIO_BUFFER,
+        1,
+    )
+    .map_err(|err| Error::Resample(err.to_string()))
+}
+
+/// Read a whole source as 16 kHz mono samples.
+pub fn read_to_end(source: &mut dyn AudioSource) -> Result<Vec<f32>> {
+    let mut resampler = MonoResampler::new(source.sample_rate(), source.channels())?;
+    let mut output = Vec::new();
+    while let Some(chunk) = source.read()? {
+        output.extend(resampler.process(&chunk)?);
+    }
+    output.extend(resampler.finish()?);
+    Ok(output)
+}
+
+/// An audio file in any format Symphonia can decode, e.g. WAV, FLAC or Ogg Vorbis.
+pub struct FileSource {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    sample_rate: usize,
+    channels: usize,
+}
+
+impl FileSource {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+            hint.with_extension(extension);
+        }
+
+        let probed = symphonia::default::get_probe().format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
+        let format = probed.format;
+
+        let track = format
+            .default_track()
+            .ok_or_else(|| Error::AudioFile("no audio track found".into()))?;
+        let sample_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or_else(|| Error::AudioFile("unknown sample rate".into()))?;
+        let channels = track
+            .codec_params
+            .channels
+            .map(|channels| channels.count())
+            .ok_or_else(|| Error::AudioFile("unknown channel layout".into()))?;
+        let track_id = track.id;
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+
+        Ok(Self {
+            format,
+            decoder,
+            track_id,
+            sample_rate: sample_rate as usize,
+            channels,
+        })
+    }
+}
+
+impl AudioSource for FileSource {
+    fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self) -> Result<Option<Vec<f32>>> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt packet, the next ones may be fine
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            buffer.copy_interleaved_ref(decoded);
+            return Ok(Some(buffer.samples().to_vec()));
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmFormat {
+    /// Signed 16-bit little-endian
+    S16le,
+    /// 32-bit float little-endian
+    F32le,
+}
+
+impl PcmFormat {
+    fn sample_size(self) -> usize {
+        match self {
+            PcmFormat::S16le => 2,
+            PcmFormat::F32le => 4,
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            PcmFormat::S16le => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            PcmFormat::F32le => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+/// Headerless interleaved PCM, e.g. piped from `arecord` or `ffmpeg` on stdin.
+pub struct PcmSource<R> {
+    reader: R,
+    format: PcmFormat,
+    sample_rate: usize,
+    channels: usize,
+}
+
+impl<R: Read> PcmSource<R> {
+    pub fn new(reader: R, format: PcmFormat, sample_rate: usize, channels: usize) -> Self {
+        Self {
+            reader,
+            format,
+            sample_rate,
+            channels,
+        }
+    }
+}
+
+impl<R: Read> AudioSource for PcmSource<R> {
+    fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self) -> Result<Option<Vec<f32>>> {
+        let sample_size = self.format.sample_size();
+        let mut bytes = Vec::with_capacity(PCM_CHUNK_FRAMES * self.channels * sample_size);
+        (&mut self.reader)
+            .take((PCM_CHUNK_FRAMES * self.channels * sample_size) as u64)
+            .read_to_end(&mut bytes)?;
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+
+        // A trailing partial sample can only be garbage, so it is dropped
+        Ok(Some(
+            bytes
+                .chunks_exact(sample_size)
+                .map(|sample| self.format.decode(sample))
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sine(sample_rate: usize, channels: usize, secs: f32) -> Vec<f32> {
+        let frames = (secs * sample_rate as f32) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                let sample = 0.5 * (2.0 * std::f32::consts::PI * 440.0 * t).sin();
+                std::iter::repeat_n(sample, channels)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_wav_file_is_resampled_to_mono_16khz() {
+        let path = std::env::temp_dir().join(format!("jarvy-source-{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44_100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for sample in sine(44_100, 2, 1.0) {
+            writer
+                .write_sample((sample * i16::MAX as f32) as i16)
+                .unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut source = FileSource::open(&path).unwrap();
+        assert_eq!(source.sample_rate(), 44_100);
+        assert_eq!(source.channels(), 2);
+
+        let audio = read_to_end(&mut source).unwrap();
+        assert_eq!(audio.len(), OUTPUT_SAMPLE_RATE);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_pcm_from_reader() {
+        let bytes = sine(16_000, 1, 0.5)
+            .into_iter()
+            .flat_map(|sample| ((sample * i16::MAX as f32) as i16).to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut source = PcmSource::new(Cursor::new(bytes), PcmFormat::S16le, 16_000, 1);
+
+        let audio = read_to_end(&mut source).unwrap();
+        assert_eq!(audio.len(), 8_000);
+        assert!(audio.iter().all(|sample| sample.abs() <= 0.5));
+    }
+
+    #[test]
+    fn test_resampler_buffers_odd_sized_input() {
+        let input = sine(48_000, 1, 1.0);
+        let mut resampler = MonoResampler::new(48_000, 1).unwrap();
+
+        let mut output = vec![];
+        for chunk in input.chunks(441) {
+            output.extend(resampler.process(chunk).unwrap());
+        }
+        output.extend(resampler.finish().unwrap());
+
+        assert_eq!(output.len(), OUTPUT_SAMPLE_RATE);
+        // The end of the input isn't lost in the resampler
+        let tail = &output[output.len() - 100..];
+        assert!(tail.iter().any(|sample| sample.abs() > 0.3));
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+
+use crate::audio_source::OUTPUT_SAMPLE_RATE;
+use crate::config::{BargeInConfig, VadConfig};
+use crate::vad::rms;
+
+// Until the echo has been measured, assume the speakers reach the mic at full level
+const INITIAL_ECHO_GAIN: f32 = 1.0;
+// How quickly the echo gain follows the room while the user is quiet
+const ECHO_ADAPTATION: f32 = 0.1;
+// The echo arrives a little after the audio is handed to the output device,
+// so the playback level is held, decaying by this much per frame
+const ECHO_HOLD: f32 = 0.9;
+
+/// Detects the user talking over the assistant's reply.
+///
+/// The microphone also picks up the reply from the speakers. That echo is
+/// estimated as the playback level times a gain learned while the user is
+/// quiet, and the user only counts as talking once they are `echo_margin`
+/// times louder than the echo, and than the noise floor, for `min_speech_ms`.
+pub struct BargeInDetector {
+    frame_len: usize,
+    min_speech_frames: usize,
+    // Enough audio to start the utterance with, including its pre-roll
+    keep_len: usize,
+    threshold_ratio: f32,
+    min_energy: f32,
+    echo_margin: f32,
+    noise_floor: f32,
+    echo_gain: f32,
+    echo_level: f32,
+
+    frame: Vec<f32>,
+    speech_frames: usize,
+    audio: VecDeque<f32>,
+}
+
+impl BargeInDetector {
+    pub fn new(vad: &VadConfig, config: &BargeInConfig) -> Self {
+        let frame_len = vad.frame_ms * OUTPUT_SAMPLE_RATE / 1000;
+        Self {
+            frame_len,
+            min_speech_frames: config.min_speech_ms.div_ceil(vad.frame_ms).max(1),
+            keep_len: (vad.pre_roll_ms + config.min_speech_ms) * OUTPUT_SAMPLE_RATE / 1000
+                + frame_len,
+            threshold_ratio: vad.threshold_ratio,
+            min_energy: vad.min_energy,
+            echo_margin: config.echo_margin,
+            noise_floor: vad.min_energy,
+            echo_gain: INITIAL_ECHO_GAIN,
+            echo_level: 0.0,
+            frame: Vec::with_capacity(frame_len),
+            speech_frames: 0,
+            audio: VecDeque::new(),
+        }
+    }
+
+    /// Get ready for the next reply. The echo gain learned so far is kept.
+    pub fn start(&mut self, noise_floor: f32) {
+        self.noise_floor = noise_floor;
+        self.echo_level = 0.0;
+        self.frame.clear();
+        self.speech_frames = 0;
+        self.audio.clear();
+    }
+
+    /// Feed microphone audio recorded while the reply plays at `playback_level` RMS.
+    ///
+    /// Returns `true` once the user is talking.
+    pub fn process(&mut self, samples: &[f32], playback_level: f32) -> bool {
+        let mut heard = false;
+        for &sample in samples {
+            // The rest of the chunk belongs to the utterance
+            self.audio.push_back(sample);
+            if heard {
+                continue;
+            }
+            self.frame.push(sample);
+            if self.frame.len() == self.frame_len {
+                let frame = std::mem::replace(&mut self.frame, Vec::with_capacity(self.frame_len));
+                heard = self.process_frame(&frame, playback_level);
+            }
+        }
+        while !heard && self.audio.len() > self.keep_len {
+            self.audio.pop_front();
+        }
+        heard
+    }
+
+    /// The audio leading up to and including the user talking.
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        self.audio.drain(..).collect()
+    }
+
+    fn process_frame(&mut self, frame: &[f32], playback_level: f32) -> bool {
+        self.echo_level = playback_level.max(self.echo_level * ECHO_HOLD);
+        let energy = rms(frame);
+        let echo = self.echo_gain * self.echo_level;
+        let threshold = (self.noise_floor * self.threshold_ratio)
+            .max(self.min_energy)
+            .max(echo * self.echo_margin);
+
+        if energy > threshold {
+            self.speech_frames += 1;
+        } else {
+            self.speech_frames = 0;
+            if self.echo_level > f32::EPSILON {
+                self.echo_gain += ECHO_ADAPTATION * (energy / self.echo_level - self.echo_gain);
+            }
+        }
+        self.speech_frames >= self.min_speech_frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(secs: f32, amplitude: f32) -> Vec<f32> {
+        let len = (secs * OUTPUT_SAMPLE_RATE as f32) as usize;
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / OUTPUT_SAMPLE_RATE as f32;
+                amplitude * (2.0 * std::f32::consts::PI * 220.0 * t).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_echo_is_not_barge_in() {
+        let mut detector = BargeInDetector::new(&VadConfig::default(), &BargeInConfig::default());
+        detector.start(0.001);
+
+        // The reply plays at 0.2 RMS and reaches the mic much quieter
+        for chunk in tone(3.0, 0.05).chunks(512) {
+            assert!(!detector.process(chunk, 0.2));
+        }
+    }
+
+    #[test]
+    fn test_talking_over_the_reply() {
+        let config = BargeInConfig::default();
+        let mut detector = BargeInDetector::new(&VadConfig::default(), &config);
+        detector.start(0.001);
+        for chunk in tone(1.0, 0.05).chunks(512) {
+            detector.process(chunk, 0.2);
+        }
+
+        let heard = tone(1.0, 0.5)
+            .chunks(512)
+            .any(|chunk| detector.process(chunk, 0.2));
+        assert!(heard);
+        let min_speech = config.min_speech_ms * OUTPUT_SAMPLE_RATE / 1000;
+        assert!(detector.take_audio().len() >= min_speech);
+    }
+}
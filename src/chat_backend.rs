@@ -0,0 +1,93 @@
+use std::pin::Pin;
+
+use async_openai::types::{ChatCompletionRequestMessage, CreateChatCompletionRequestArgs};
+use async_openai::Client;
+use futures::{Stream, StreamExt, TryFutureExt};
+
+use crate::error::Result;
+use crate::traits::ChatBackend;
+
+pub type TokenResult = Result<String>;
+pub type TokenStream = Pin<Box<dyn Stream<Item = TokenResult> + Send>>;
+
+/// Chat completions from the OpenAI API.
+///
+/// The API key is read from the `OPENAI_API_KEY` environment variable.
+pub struct OpenAiBackend {
+    client: Client,
+    model: String,
+}
+
+impl OpenAiBackend {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            client: Client::new(),
+            model: model.into(),
+        }
+    }
+}
+
+impl ChatBackend for OpenAiBackend {
+    fn stream_chat(&self, messages: Vec<ChatCompletionRequestMessage>) -> TokenStream {
+        stream_from_client(self.client.clone(), self.model.clone(), messages)
+    }
+}
+
+/// Chat completions from a self-hosted server that speaks the OpenAI
+/// chat completions protocol, e.g. llama.cpp server or Ollama.
+pub struct LocalBackend {
+    client: Client,
+    model: String,
+}
+
+impl LocalBackend {
+    /// `api_base` is the URL up to and including the version prefix,
+    /// e.g. `http://localhost:11434/v1` for Ollama.
+    pub fn new(api_base: impl Into<String>, model: impl Into<String>) -> Self {
+        // Local servers ignore the key, but the client always sends one
+        let client = Client::new().with_api_base(api_base).with_api_key("local");
+        Self {
+            client,
+            model: model.into(),
+        }
+    }
+}
+
+impl ChatBackend for LocalBackend {
+    fn stream_chat(&self, messages: Vec<ChatCompletionRequestMessage>) -> TokenStream {
+        stream_from_client(self.client.clone(), self.model.clone(), messages)
+    }
+}
+
+/// Make a streaming chat request and keep only the content tokens.
+///
+/// The request is sent lazily, when the stream is first polled.
+fn stream_from_client(
+    client: Client,
+    model: String,
+    messages: Vec<ChatCompletionRequestMessage>,
+) -> TokenStream {
+    let response = async move {
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(model)
+            .messages(messages)
+            .stream(true)
+            .build()?;
+        client.chat().create_stream(request).await
+    };
+
+    let tokens = response
+        .try_flatten_stream()
+        .filter_map(|result| async move {
+            match result {
+                Ok(mut response) => response
+                    .choices
+                    .pop()
+                    .and_then(|choice| choice.delta.content)
+                    .map(Ok),
+                Err(err) => Some(Err(err.into())),
+            }
+        });
+
+    Box::pin(tokens)
+}
@@ -0,0 +1,216 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::audio_source::PcmFormat;
+use crate::config::{Config, Trigger, TtsEngineKind};
+
+#[derive(Parser, Debug)]
+#[command(name = "jarvy", about = "A voice assistant for pair programming")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Where the user's turns come from in the chat loop
+    #[arg(long, global = true, value_enum)]
+    pub input: Option<InputSource>,
+
+    /// Speech engine used to read replies out loud
+    #[arg(long, global = true, value_enum)]
+    pub tts: Option<TtsEngineKind>,
+
+    /// Chat model name
+    #[arg(long, global = true)]
+    pub model: Option<String>,
+
+    /// Path to a ggml Whisper model
+    #[arg(long, global = true)]
+    pub whisper_model: Option<PathBuf>,
+
+    /// Spoken language, e.g. "de", or "auto" to detect it
+    #[arg(long, global = true)]
+    pub language: Option<String>,
+
+    /// Translate speech into English instead of transcribing it
+    #[arg(long, global = true)]
+    pub translate: bool,
+
+    /// Record from the input device whose name contains this
+    #[arg(long, global = true)]
+    pub input_device: Option<String>,
+
+    /// Record while Space is held (or between two presses) instead of until silence
+    #[arg(long, global = true)]
+    pub push_to_talk: bool,
+
+    /// Only listen to utterances starting with the wake word, "Jarvy" by default
+    #[arg(long, global = true, conflicts_with = "push_to_talk")]
+    pub wake_word: bool,
+
+    /// Interrupt the reply by talking over it
+    #[arg(long, global = true)]
+    pub barge_in: bool,
+
+    /// Show partial transcriptions while speaking
+    #[arg(long, global = true)]
+    pub streaming: bool,
+
+    /// Directory where code blocks and sessions are written
+    #[arg(long, short, global = true)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Write speech to WAV files in this directory instead of playing it
+    #[arg(long, global = true)]
+    pub speech_dir: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Talk to the assistant
+    Chat,
+    /// Type to the assistant
+    Text,
+    /// Transcribe an audio file, or raw PCM from stdin, and print the text
+    Transcribe {
+        /// WAV, FLAC or Ogg file, or `-` for headerless PCM on stdin
+        path: PathBuf,
+        /// Sample format of PCM on stdin
+        #[arg(long, value_enum, default_value = "s16le")]
+        format: PcmFormat,
+        /// Sample rate of PCM on stdin
+        #[arg(long, default_value_t = 16_000, value_parser = at_least_one)]
+        sample_rate: usize,
+        /// Channel count of PCM on stdin
+        #[arg(long, default_value_t = 1, value_parser = at_least_one)]
+        channels: usize,
+        /// Print each segment with its start and end time
+        #[arg(long)]
+        timestamps: bool,
+    },
+    /// Read some text out loud
+    Say { text: String },
+    /// Print and read out a saved session
+    Replay { session: PathBuf },
+    /// List the input devices
+    Devices,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputSource {
+    /// Speak every turn
+    Voice,
+    /// Type every turn
+    Keyboard,
+    /// Speak, with Tab to switch between speaking and typing
+    Hybrid,
+}
+
+impl Cli {
+    /// Command-line flags take precedence over every config layer.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(engine) = self.tts {
+            config.tts.engine = engine;
+        }
+        if let Some(model) = &self.model {
+            config.llm.model = model.clone();
+        }
+        if let Some(model_path) = &self.whisper_model {
+            config.stt.model_path = Some(model_path.clone());
+        }
+        if let Some(input_device) = &self.input_device {
+            config.stt.input_device = Some(input_device.clone());
+        }
+        if let Some(language) = &self.language {
+            config.stt.whisper.language = language.clone();
+        }
+        if self.translate {
+            config.stt.whisper.translate = true;
+        }
+        if self.push_to_talk {
+            config.stt.trigger = Trigger::PushToTalk;
+        }
+        if self.wake_word {
+            config.stt.trigger = Trigger::WakeWord;
+        }
+        if self.barge_in {
+            config.stt.barge_in.enabled = true;
+        }
+        if self.streaming {
+            config.stt.streaming = true;
+        }
+        if let Some(output_dir) = &self.output_dir {
+            config.code.home_dir = Some(output_dir.clone());
+        }
+        if let Some(speech_dir) = &self.speech_dir {
+            config.tts.output_dir = Some(speech_dir.clone());
+        }
+    }
+
+    pub fn input_source(&self) -> InputSource {
+        match (&self.command, self.input) {
+            (Command::Text, _) => InputSource::Keyboard,
+            (_, Some(input)) => input,
+            (_, None) => InputSource::Voice,
+        }
+    }
+}
+
+/// A sample rate or channel count, which can't be zero.
+fn at_least_one(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(number) => Ok(number),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_override_config() {
+        let cli =
+            Cli::try_parse_from(["jarvy", "text", "--model", "gpt-4", "-o", "/tmp/out"]).unwrap();
+        let mut config = Config::default();
+        cli.apply(&mut config);
+
+        assert_eq!(cli.input_source(), InputSource::Keyboard);
+        assert_eq!(config.llm.model, "gpt-4");
+        assert_eq!(config.code.home_dir, Some(PathBuf::from("/tmp/out")));
+    }
+
+    #[test]
+    fn test_transcribe_stdin_pcm() {
+        let cli = Cli::try_parse_from([
+            "jarvy",
+            "transcribe",
+            "-",
+            "--format",
+            "f32le",
+            "--sample-rate",
+            "48000",
+        ])
+        .unwrap();
+
+        match cli.command {
+            Command::Transcribe {
+                path,
+                format,
+                sample_rate,
+                channels,
+                ..
+            } => {
+                assert_eq!(path, PathBuf::from("-"));
+                assert_eq!(format, PcmFormat::F32le);
+                assert_eq!(sample_rate, 48_000);
+                assert_eq!(channels, 1);
+            }
+            command => panic!("unexpected command {:?}", command),
+        }
+
+        for flag in ["--sample-rate", "--channels"] {
+            assert!(Cli::try_parse_from(["jarvy", "transcribe", "-", flag, "0"]).is_err());
+        }
+    }
+}
@@ -1,90 +1,307 @@
 use std::collections::VecDeque;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
 
+use crate::error::Result;
+
+// Info strings taken as a language when they come before a `-`, so that
+// `docker-compose.yml` stays a file name
+const LANGUAGES: &[&str] = &[
+    "bash",
+    "c",
+    "cpp",
+    "csharp",
+    "css",
+    "dart",
+    "dockerfile",
+    "elixir",
+    "go",
+    "haskell",
+    "html",
+    "java",
+    "javascript",
+    "js",
+    "json",
+    "jsx",
+    "kotlin",
+    "lua",
+    "make",
+    "makefile",
+    "markdown",
+    "md",
+    "ocaml",
+    "php",
+    "powershell",
+    "python",
+    "py",
+    "ruby",
+    "rust",
+    "scala",
+    "scss",
+    "sh",
+    "shell",
+    "sql",
+    "swift",
+    "toml",
+    "ts",
+    "tsx",
+    "typescript",
+    "xml",
+    "yaml",
+    "yml",
+    "zsh",
+];
+
+/// A code block of the reply.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CodeBlock {
+    pub language: Option<String>,
+    pub filename: Option<String>,
+    pub code: String,
+}
+
+impl CodeBlock {
+    /// A block opened by a fence with `info` after it, as asked for in the
+    /// system prompt: `rust-src/main.rs`, or `rust`, `src/main.rs`, `rust src/main.rs`.
+    ///
+    /// What follows the language is only a file name if it looks like a path,
+    /// so `shell-session` and `objective-c` are languages.
+    pub fn new(info: &str) -> Self {
+        let info = info.trim();
+        let (language, filename) = match info.split_once(char::is_whitespace) {
+            Some((language, rest)) if is_path(rest.trim()) => (language, rest.trim()),
+            Some(_) => (info, ""),
+            None => match info.split_once('-') {
+                Some((language, rest)) if is_path(rest) && is_language(language, rest) => {
+                    (language, rest)
+                }
+                _ if is_path(info) => ("", info),
+                _ => (info, ""),
+            },
+        };
+        let some = |text: &str| (!text.is_empty()).then(|| text.to_string());
+        Self {
+            language: some(language),
+            filename: some(filename),
+            code: String::new(),
+        }
+    }
+}
+
+fn is_path(text: &str) -> bool {
+    text.contains('.') || text.contains('/')
+}
+
+/// Whether `word` names the language of `filename`, e.g. `rust` or `zig` for `build.zig`.
+fn is_language(word: &str, filename: &str) -> bool {
+    let word = word.to_lowercase();
+    LANGUAGES.contains(&word.as_str())
+        || Path::new(filename)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(&word))
+}
+
+/// Saves the code blocks of a reply as soon as they are closed, and asks what
+/// to do with those without a file name once the reply is over.
 pub struct CodeAssistant {
-    char_buffer: Vec<char>,
-    snippets_buffer: VecDeque<String>,
+    // The block being streamed
+    block: Option<CodeBlock>,
+    // Blocks without a file name, to ask about
+    unnamed: VecDeque<CodeBlock>,
     home_dir: PathBuf,
 }
 
 impl CodeAssistant {
-    pub fn new(home_dir: PathBuf) -> Self {
-        std::fs::create_dir_all(&home_dir).expect("Could not create home directory");
-        Self {
-            char_buffer: Vec::new(),
-            snippets_buffer: VecDeque::new(),
+    pub fn new(home_dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&home_dir)?;
+        Ok(Self {
+            block: None,
+            unnamed: VecDeque::new(),
             home_dir,
-        }
+        })
     }
 }
 
 impl CodeAssistant {
-    pub fn flush(&mut self) {
-        if !self.char_buffer.is_empty() {
-            self.snippets_buffer
-                .push_back(self.char_buffer.iter().collect());
-            self.char_buffer.clear();
+    /// Start a block. One left open, by a reply that broke off, is dropped.
+    pub fn open(&mut self, info: &str) {
+        self.block = Some(CodeBlock::new(info));
+    }
+
+    pub fn push_line(&mut self, line: &str) {
+        if let Some(block) = &mut self.block {
+            block.code.push_str(line);
+            block.code.push('\n');
+        }
+    }
+
+    /// Save the open block, if there is one, or keep it to ask about.
+    pub fn close(&mut self) -> Result<()> {
+        match self.block.take() {
+            Some(CodeBlock {
+                filename: Some(filename),
+                code,
+                ..
+            }) => self.save(&filename, &code),
+            Some(block) => {
+                self.unnamed.push_back(block);
+                Ok(())
+            }
+            None => Ok(()),
         }
-        self.flush_code_snippets();
     }
-    pub fn push(&mut self, chars: &[char]) {
-        self.snippets_buffer.push_back(chars.iter().collect());
+
+    /// Drop the block being streamed.
+    pub fn discard(&mut self) {
+        self.block = None;
     }
-    fn flush_code_snippets(&mut self) {
-        while let Some(code_snippet) = self.snippets_buffer.pop_front() {
-            let mut lines = code_snippet.lines();
-            let language_and_filename = lines.next().unwrap().trim_start_matches("```").to_string();
-            let mut file_details = language_and_filename
-                .split('-')
-                .map(|s| s.trim())
-                .collect::<Vec<_>>();
-
-            let (language, filename) = if file_details.len() == 2 {
-                (file_details.remove(0), Some(file_details.remove(0)))
-            } else {
-                (file_details[0], None)
-            };
-
-            let code = lines.collect::<Vec<_>>().join("\n");
-
-            if let Some(filename) = filename {
-                let filepath = self.home_dir.join(filename);
-                std::fs::write(filepath, code).unwrap();
-            } else {
-                let mut is_file = String::new();
-                loop {
-                    println!("file or shell or drop? ");
-                    std::io::stdin().read_line(&mut is_file).unwrap();
-                    is_file = is_file.trim().to_string();
-                    if is_file == "file" {
-                        let mut filename = String::new();
-                        println!("filename? ");
-                        std::io::stdin().read_line(&mut filename).unwrap();
-                        let filepath = self.home_dir.join(filename.trim());
-                        std::fs::write(filepath, code).unwrap();
+
+    /// Ask whether to save, run or drop each block without a file name.
+    pub fn ask(&mut self) -> Result<()> {
+        // Taken up front, so none carry over to the next reply if this fails
+        for block in std::mem::take(&mut self.unnamed) {
+            loop {
+                match &block.language {
+                    Some(language) => println!("{} block: file or shell or drop? ", language),
+                    None => println!("file or shell or drop? "),
+                }
+                // The input was closed, which ends the session
+                let Some(answer) = read_answer()? else {
+                    return Ok(());
+                };
+                if answer == "file" {
+                    println!("filename? (empty to drop)");
+                    let Some(filename) = read_answer()? else {
+                        return Ok(());
+                    };
+                    if filename.is_empty() {
                         break;
-                    } else if is_file == "shell" {
-                        // Blocking call
-                        let output = Command::new("sh").arg("-c").arg(&code).output().unwrap();
-                        let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                        let _stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
-                        let exit_code = output.status.code().unwrap_or(-1);
-                        println!("{} {}", exit_code, stdout);
-
-                        // Break so that we can give (negative) feedback to the model
-                        // Otherwise, the model will keep on generating
-                        // and we won't be able to see the output
-                        if exit_code == 0 {
-                            // Continue execution
-                        } else {
-                            break;
-                        }
-                    } else {
-                        // Keep asking for input
                     }
+                    match self.save(&filename, &block.code) {
+                        Ok(()) => break,
+                        // Ask again for this block
+                        Err(err) => eprintln!("Error: {}", err),
+                    }
+                } else if answer == "shell" {
+                    // Blocking call
+                    let output = Command::new("sh").arg("-c").arg(&block.code).output()?;
+                    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                    let exit_code = output.status.code().unwrap_or(-1);
+                    println!("{} {}", exit_code, stdout);
+                    break;
+                } else if answer == "drop" {
+                    break;
                 }
+                // Keep asking for input
             }
         }
+        Ok(())
+    }
+
+    /// Write `code` to `filename` under the home directory, which it may not leave.
+    fn save(&self, filename: &str, code: &str) -> Result<()> {
+        let escapes = Path::new(filename).components().any(|component| {
+            matches!(
+                component,
+                Component::RootDir | Component::Prefix(_) | Component::ParentDir
+            )
+        });
+        if escapes || filename.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not saving code outside the code directory: {:?}", filename),
+            )
+            .into());
+        }
+        let filepath = self.home_dir.join(filename);
+        if let Some(parent) = filepath.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(filepath, code)?;
+        Ok(())
+    }
+}
+
+/// A trimmed line from stdin, none at the end of input.
+fn read_answer() -> Result<Option<String>> {
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(answer.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fence_info() {
+        let block = |info| {
+            let block = CodeBlock::new(info);
+            (block.language, block.filename)
+        };
+        let some = |text: &str| Some(text.to_string());
+        assert_eq!(
+            block("rust-src/main.rs"),
+            (some("rust"), some("src/main.rs"))
+        );
+        assert_eq!(
+            block("python my-script.py"),
+            (some("python"), some("my-script.py"))
+        );
+        assert_eq!(block("zig-build.zig"), (some("zig"), some("build.zig")));
+        assert_eq!(block("sh"), (some("sh"), None));
+        assert_eq!(block("main.rs"), (None, some("main.rs")));
+        assert_eq!(
+            block("docker-compose.yml"),
+            (None, some("docker-compose.yml"))
+        );
+        assert_eq!(block("shell-session"), (some("shell-session"), None));
+        assert_eq!(block("objective-c"), (some("objective-c"), None));
+        assert_eq!(block(""), (None, None));
+    }
+
+    #[test]
+    fn test_each_block_is_saved_to_its_file() {
+        let dir = std::env::temp_dir().join(format!("jarvy-code-{}", std::process::id()));
+        let mut assistant = CodeAssistant::new(dir.clone()).unwrap();
+        // Left open by a reply that broke off
+        assistant.open("rust-src/lib.rs");
+        assistant.push_line("pub mod");
+        assistant.open("rust-src/app.rs");
+        assistant.push_line("pub fn run() {}");
+        assistant.close().unwrap();
+        assistant.open("rust-src/lib.rs");
+        assistant.push_line("pub mod app;");
+        assistant.close().unwrap();
+        assistant.open("toml-Cargo.toml");
+        assistant.push_line("[package]");
+        assistant.discard();
+        assistant.close().unwrap();
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("src/lib.rs"), "pub mod app;\n");
+        assert_eq!(read("src/app.rs"), "pub fn run() {}\n");
+        assert!(!dir.join("Cargo.toml").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_files_outside_home_dir_are_refused() {
+        let dir = std::env::temp_dir().join(format!("jarvy-escape-{}", std::process::id()));
+        let mut assistant = CodeAssistant::new(dir.join("home")).unwrap();
+        for info in [
+            "rust-../main.rs",
+            "rust-/tmp/main.rs",
+            "rust src/../../main.rs",
+        ] {
+            assistant.open(info);
+            assistant.push_line("fn main() {}");
+            assert!(assistant.close().is_err(), "{}", info);
+        }
+        assert!(!dir.join("main.rs").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,685 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+const PROJECT_CONFIG_FILE: &str = "jarvy.toml";
+
+const DEFAULT_SYSTEM_PROMPT: &str = r#"You are going to be pair-programme with me. I need you to be less verbose in your explanations.
+
+        Put each file in its own code block, as many as you need.
+
+        Please specify the language and the filename of each code block at the backticks
+
+        ```<language>-<filename>
+        <code>
+        ```.
+
+            Leave the filename out of commands meant for the shell. Changes to a file should rewrite its code block entirely. Any suggestions or questions you have, please ask me. I'll be happy to answer them. Let's get started!"#;
+
+/// Settings for the whole session.
+///
+/// Layers are applied in order, later ones winning:
+/// built-in defaults, the user config (`~/.config/jarvy/config.toml`),
+/// the project config (`./jarvy.toml`), then `JARVY_*` environment variables.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub code: CodeConfig,
+    pub stt: SttConfig,
+    pub llm: LlmConfig,
+    pub tts: TtsConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CodeConfig {
+    /// Directory where code blocks are written
+    pub home_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SttConfig {
+    /// Path to a ggml Whisper model
+    pub model_path: Option<PathBuf>,
+    /// Part of the microphone's name, the default input device if unset
+    pub input_device: Option<String>,
+    pub trigger: Trigger,
+    /// Said before each request when the trigger is `wake-word`
+    pub wake_word: String,
+    /// Said during the reply to stop it, empty to only stop it with Ctrl-C
+    pub stop_word: String,
+    /// Show partial transcriptions while the user is still speaking,
+    /// only with the voice trigger
+    pub streaming: bool,
+    /// Transcripts with a lower mean token probability are shown for confirmation
+    pub min_confidence: f32,
+    /// Transcripts more likely than this to be noise are dropped
+    pub max_no_speech_prob: f32,
+    pub vad: VadConfig,
+    pub barge_in: BargeInConfig,
+    pub whisper: WhisperConfig,
+}
+
+impl Default for SttConfig {
+    fn default() -> Self {
+        Self {
+            model_path: None,
+            input_device: None,
+            trigger: Trigger::Voice,
+            wake_word: "Jarvy".to_string(),
+            stop_word: "stop".to_string(),
+            streaming: false,
+            min_confidence: 0.5,
+            max_no_speech_prob: 0.6,
+            vad: VadConfig::default(),
+            barge_in: BargeInConfig::default(),
+            whisper: WhisperConfig::default(),
+        }
+    }
+}
+
+/// Decoding options passed to Whisper on every run.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WhisperConfig {
+    /// Spoken language code such as "en" or "de", or "auto" to detect it
+    pub language: String,
+    /// Translate into English instead of transcribing in the spoken language
+    pub translate: bool,
+    /// Beam search width, greedy decoding if unset
+    pub beam_size: Option<usize>,
+    pub threads: usize,
+    /// Text the transcription is conditioned on, e.g. names Whisper would misspell
+    pub initial_prompt: Option<String>,
+    /// Sampling temperature, 0 for the most likely transcription
+    pub temperature: f32,
+}
+
+impl Default for WhisperConfig {
+    fn default() -> Self {
+        Self {
+            language: "en".to_string(),
+            translate: false,
+            beam_size: None,
+            threads: std::thread::available_parallelism()
+                .map(|threads| threads.get().min(4))
+                .unwrap_or(1),
+            initial_prompt: None,
+            temperature: 0.0,
+        }
+    }
+}
+
+/// What starts and ends a recording.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Trigger {
+    /// Speech starts it and silence ends it
+    #[default]
+    Voice,
+    /// Holding the talk key, or pressing it twice
+    PushToTalk,
+    /// Like `Voice`, but only utterances starting with the wake word count
+    WakeWord,
+}
+
+/// Voice activity detection, see `vad::VoiceActivityDetector`.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct VadConfig {
+    pub frame_ms: usize,
+    /// Silence after speech before the utterance ends
+    pub hangover_ms: usize,
+    /// Audio kept from before speech starts
+    pub pre_roll_ms: usize,
+    pub max_utterance_secs: f32,
+    /// Background audio measured at startup to set the noise floor
+    pub calibration_ms: usize,
+    /// Speech must be this many times louder than the noise floor
+    pub threshold_ratio: f32,
+    /// Lowest RMS energy counted as speech, for very quiet rooms
+    pub min_energy: f32,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            frame_ms: 30,
+            hangover_ms: 1000,
+            pre_roll_ms: 300,
+            max_utterance_secs: 60.0,
+            calibration_ms: 500,
+            threshold_ratio: 3.0,
+            min_energy: 0.005,
+        }
+    }
+}
+
+/// Talking over the reply to interrupt it, see `barge_in::BargeInDetector`.
+///
+/// Only used with the voice trigger.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BargeInConfig {
+    pub enabled: bool,
+    /// Talking this long over the reply interrupts it
+    pub min_speech_ms: usize,
+    /// Speech must be this many times louder than the reply's echo
+    pub echo_margin: f32,
+}
+
+impl Default for BargeInConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_speech_ms: 300,
+            echo_margin: 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LlmBackendKind {
+    #[default]
+    OpenAi,
+    Local,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LlmConfig {
+    pub backend: LlmBackendKind,
+    pub model: String,
+    /// Base URL of an OpenAI-compatible server, required for the local backend
+    pub api_base: Option<String>,
+    pub system_prompt: String,
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        Self {
+            backend: LlmBackendKind::OpenAi,
+            model: "gpt-3.5-turbo".to_string(),
+            api_base: None,
+            system_prompt: DEFAULT_SYSTEM_PROMPT.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TtsEngineKind {
+    /// ElevenLabs text-to-speech API
+    #[default]
+    #[value(name = "elevenlabs")]
+    ElevenLabs,
+    /// OpenAI text-to-speech API
+    #[serde(rename = "openai")]
+    #[value(name = "openai")]
+    OpenAi,
+    /// Piper neural voices, offline
+    Piper,
+    /// The `espeak-ng` command, offline
+    Espeak,
+    /// The macOS `say` command
+    Say,
+    /// No speech, replies are only printed
+    #[serde(rename = "none")]
+    #[value(name = "none")]
+    Silent,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TtsConfig {
+    pub engine: TtsEngineKind,
+    /// ElevenLabs endpoint, the voice id is appended
+    pub api_url: String,
+    pub voice_id: String,
+    /// Read from `ELEVENLABS_API_KEY`, never from a file
+    #[serde(skip)]
+    pub api_key: Option<String>,
+    /// Write speech to WAV files under this directory instead of playing it
+    pub output_dir: Option<PathBuf>,
+    pub openai: OpenAiTtsConfig,
+    pub piper: PiperConfig,
+    pub espeak: EspeakConfig,
+    pub say: SayConfig,
+}
+
+impl Default for TtsConfig {
+    fn default() -> Self {
+        Self {
+            engine: TtsEngineKind::ElevenLabs,
+            api_url: "https://api.elevenlabs.io/v1/text-to-speech/".to_string(),
+            voice_id: "EXAVITQu4vr4xnSDxMaL".to_string(),
+            api_key: None,
+            output_dir: None,
+            openai: OpenAiTtsConfig::default(),
+            piper: PiperConfig::default(),
+            espeak: EspeakConfig::default(),
+            say: SayConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAiTtsConfig {
+    pub api_url: String,
+    /// "tts-1", or "tts-1-hd" for better quality at higher latency
+    pub model: String,
+    pub voice: String,
+    /// Read from `OPENAI_API_KEY`, never from a file
+    #[serde(skip)]
+    pub api_key: Option<String>,
+}
+
+impl Default for OpenAiTtsConfig {
+    fn default() -> Self {
+        Self {
+            api_url: "https://api.openai.com/v1/audio/speech".to_string(),
+            model: "tts-1".to_string(),
+            voice: "alloy".to_string(),
+            api_key: None,
+        }
+    }
+}
+
+/// Runs the `piper` command, see https://github.com/rhasspy/piper for voices.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PiperConfig {
+    pub executable: String,
+    /// Voice model, e.g. `en_US-lessac-medium.onnx`, with its `.onnx.json` next to it
+    pub model: Option<PathBuf>,
+    /// Speaker id, for voices with several speakers
+    pub speaker: Option<u32>,
+    /// Above 1 speaks slower, below 1 faster
+    pub length_scale: f32,
+}
+
+impl Default for PiperConfig {
+    fn default() -> Self {
+        Self {
+            executable: "piper".to_string(),
+            model: None,
+            speaker: None,
+            length_scale: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EspeakConfig {
+    /// See `espeak-ng --voices`
+    pub voice: String,
+    pub words_per_minute: u32,
+}
+
+impl Default for EspeakConfig {
+    fn default() -> Self {
+        Self {
+            voice: "en-us".to_string(),
+            words_per_minute: 175,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SayConfig {
+    /// See `say -v '?'`
+    pub voice: String,
+    pub rate: u32,
+}
+
+impl Default for SayConfig {
+    fn default() -> Self {
+        Self {
+            voice: "samantha".to_string(),
+            rate: 200,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "could not read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => {
+                write!(f, "invalid config {}: {}", path.display(), err)
+            }
+            ConfigError::Invalid(msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Load the user and project config files and the environment.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut paths = vec![];
+        if let Some(user_config) = user_config_path() {
+            paths.push(user_config);
+        }
+        paths.push(PathBuf::from(PROJECT_CONFIG_FILE));
+
+        let mut config = Self::from_files(&paths)?;
+        config.apply_env(|name| std::env::var(name).ok());
+        Ok(config)
+    }
+
+    /// Merge the given files in order. Missing files are skipped.
+    pub fn from_files(paths: &[PathBuf]) -> Result<Self, ConfigError> {
+        let mut merged = toml::Table::new();
+        for path in paths.iter().filter(|path| path.exists()) {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|err| ConfigError::Read(path.clone(), err))?;
+            let table: toml::Table =
+                toml::from_str(&contents).map_err(|err| ConfigError::Parse(path.clone(), err))?;
+            merge_tables(&mut merged, table);
+        }
+
+        let path = paths.last().cloned().unwrap_or_default();
+        toml::Value::Table(merged)
+            .try_into()
+            .map_err(|err| ConfigError::Parse(path, err))
+    }
+
+    /// Override settings from `JARVY_*` variables looked up with `var`.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) {
+        if let Some(home_dir) = var("JARVY_HOME_DIR") {
+            self.code.home_dir = Some(home_dir.into());
+        }
+        if let Some(model_path) = var("JARVY_WHISPER_MODEL") {
+            self.stt.model_path = Some(model_path.into());
+        }
+        if let Some(api_base) = var("JARVY_LLM_BASE_URL") {
+            self.llm.backend = LlmBackendKind::Local;
+            self.llm.api_base = Some(api_base);
+        }
+        if let Some(model) = var("JARVY_LLM_MODEL") {
+            self.llm.model = model;
+        }
+        if let Some(model) = var("JARVY_PIPER_MODEL") {
+            self.tts.piper.model = Some(model.into());
+        }
+        if let Some(voice_id) = var("JARVY_VOICE_ID") {
+            self.tts.voice_id = voice_id;
+        }
+        self.tts.api_key = var("ELEVENLABS_API_KEY");
+        self.tts.openai.api_key = var("OPENAI_API_KEY");
+    }
+
+    /// Check everything the chat loop needs before it starts.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.validate_code()?;
+        self.validate_llm()?;
+        self.validate_tts()
+    }
+
+    pub fn validate_code(&self) -> Result<(), ConfigError> {
+        if self.code.home_dir.is_none() {
+            return Err(ConfigError::Invalid("code.home_dir is not set".into()));
+        }
+        Ok(())
+    }
+
+    pub fn validate_stt(&self) -> Result<(), ConfigError> {
+        match &self.stt.model_path {
+            None => Err(ConfigError::Invalid("stt.model_path is not set".into())),
+            Some(path) if !path.is_file() => Err(ConfigError::Invalid(format!(
+                "Whisper model {} does not exist",
+                path.display()
+            ))),
+            Some(_)
+                if self.stt.trigger == Trigger::WakeWord
+                    && !self.stt.wake_word.contains(char::is_alphanumeric) =>
+            {
+                Err(ConfigError::Invalid("stt.wake_word is empty".into()))
+            }
+            Some(_) => {
+                self.validate_vad()?;
+                self.validate_whisper()
+            }
+        }
+    }
+
+    pub fn validate_vad(&self) -> Result<(), ConfigError> {
+        let vad = &self.stt.vad;
+        if vad.frame_ms == 0 || vad.hangover_ms < vad.frame_ms {
+            return Err(ConfigError::Invalid(
+                "stt.vad.frame_ms must be positive and no longer than stt.vad.hangover_ms".into(),
+            ));
+        }
+        if vad.max_utterance_secs <= 0.0 || vad.threshold_ratio <= 0.0 {
+            return Err(ConfigError::Invalid(
+                "stt.vad.max_utterance_secs and stt.vad.threshold_ratio must be positive".into(),
+            ));
+        }
+        if self.stt.barge_in.echo_margin <= 0.0 {
+            return Err(ConfigError::Invalid(
+                "stt.barge_in.echo_margin must be positive".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn validate_whisper(&self) -> Result<(), ConfigError> {
+        let whisper = &self.stt.whisper;
+        if whisper.language != "auto" && whisper_rs::get_lang_id(&whisper.language).is_none() {
+            return Err(ConfigError::Invalid(format!(
+                "unknown stt.whisper.language {:?}",
+                whisper.language
+            )));
+        }
+        if whisper.threads == 0 || whisper.beam_size == Some(0) {
+            return Err(ConfigError::Invalid(
+                "stt.whisper.threads and stt.whisper.beam_size must be positive".into(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&whisper.temperature) {
+            return Err(ConfigError::Invalid(
+                "stt.whisper.temperature must be between 0 and 1".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn validate_llm(&self) -> Result<(), ConfigError> {
+        if self.llm.model.is_empty() {
+            return Err(ConfigError::Invalid("llm.model is empty".into()));
+        }
+        if self.llm.backend == LlmBackendKind::Local && self.llm.api_base.is_none() {
+            return Err(ConfigError::Invalid(
+                "llm.api_base is required for the local backend".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn validate_tts(&self) -> Result<(), ConfigError> {
+        match self.tts.engine {
+            TtsEngineKind::ElevenLabs => {
+                if self.tts.voice_id.is_empty() {
+                    return Err(ConfigError::Invalid("tts.voice_id is empty".into()));
+                }
+                if self.tts.api_key.is_none() {
+                    return Err(ConfigError::Invalid(
+                        "ELEVENLABS_API_KEY must be set".into(),
+                    ));
+                }
+            }
+            TtsEngineKind::OpenAi => {
+                if self.tts.openai.api_key.is_none() {
+                    return Err(ConfigError::Invalid("OPENAI_API_KEY must be set".into()));
+                }
+            }
+            TtsEngineKind::Piper => {
+                let Some(model) = &self.tts.piper.model else {
+                    return Err(ConfigError::Invalid("tts.piper.model is not set".into()));
+                };
+                if !model.is_file() {
+                    return Err(ConfigError::Invalid(format!(
+                        "tts.piper.model {} does not exist",
+                        model.display()
+                    )));
+                }
+                if self.tts.piper.length_scale <= 0.0 {
+                    return Err(ConfigError::Invalid(
+                        "tts.piper.length_scale must be positive".into(),
+                    ));
+                }
+            }
+            TtsEngineKind::Espeak | TtsEngineKind::Say | TtsEngineKind::Silent => {}
+        }
+        Ok(())
+    }
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_dir.join("jarvy").join("config.toml"))
+}
+
+/// Recursively merge `overlay` into `base`, with `overlay` winning.
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own for each test, removed at its end.
+    fn config_dir(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("jarvy-config-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_config(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_project_config_overrides_user_config() {
+        let dir = config_dir("layers");
+        let user = write_config(
+            &dir,
+            "user.toml",
+            "[llm]\nmodel = \"gpt-4\"\n[tts]\nvoice_id = \"user-voice\"\n",
+        );
+        let project = write_config(&dir, "project.toml", "[llm]\nmodel = \"llama2\"\n");
+
+        let config = Config::from_files(&[user, project]).unwrap();
+
+        assert_eq!(config.llm.model, "llama2");
+        assert_eq!(config.tts.voice_id, "user-voice");
+        assert_eq!(config.llm.system_prompt, DEFAULT_SYSTEM_PROMPT);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_key_is_rejected() {
+        let dir = config_dir("typo");
+        let path = write_config(&dir, "typo.toml", "[llm]\nmodle = \"gpt-4\"\n");
+        assert!(matches!(
+            Config::from_files(&[path]),
+            Err(ConfigError::Parse(..))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let mut config = Config::default();
+        config.apply_env(|name| match name {
+            "JARVY_LLM_BASE_URL" => Some("http://localhost:11434/v1".into()),
+            "JARVY_LLM_MODEL" => Some("mistral".into()),
+            _ => None,
+        });
+
+        assert_eq!(config.llm.backend, LlmBackendKind::Local);
+        assert_eq!(config.llm.model, "mistral");
+        assert_eq!(config.tts.api_key, None);
+    }
+
+    #[test]
+    fn test_validate_reports_missing_settings() {
+        let config = Config::default();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_whisper_language() {
+        let dir = config_dir("whisper");
+        let path = write_config(&dir, "whisper.toml", "[stt.whisper]\nlanguage = \"de\"\n");
+        let mut config = Config::from_files(&[path]).unwrap();
+        assert_eq!(config.stt.whisper.language, "de");
+        assert!(config.validate_whisper().is_ok());
+
+        config.stt.whisper.language = "auto".into();
+        assert!(config.validate_whisper().is_ok());
+
+        config.stt.whisper.language = "klingon".into();
+        assert!(matches!(
+            config.validate_whisper(),
+            Err(ConfigError::Invalid(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_tts_engine() {
+        let dir = config_dir("tts");
+        let path = write_config(
+            &dir,
+            "tts.toml",
+            "[tts]\nengine = \"openai\"\n[tts.openai]\nvoice = \"nova\"\n",
+        );
+        let mut config = Config::from_files(&[path]).unwrap();
+        assert_eq!(config.tts.engine, TtsEngineKind::OpenAi);
+        assert_eq!(config.tts.openai.voice, "nova");
+        assert!(matches!(
+            config.validate_tts(),
+            Err(ConfigError::Invalid(_))
+        ));
+
+        config.apply_env(|name| (name == "OPENAI_API_KEY").then(|| "sk-test".into()));
+        assert!(config.validate_tts().is_ok());
+
+        config.tts.engine = TtsEngineKind::Silent;
+        config.tts.openai.api_key = None;
+        assert!(config.validate_tts().is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
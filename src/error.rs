@@ -0,0 +1,94 @@
+use std::fmt;
+use std::io;
+
+use crate::config::ConfigError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Everything that can go wrong during a session.
+///
+/// None of these should end the chat loop except `EndOfInput`, or the input
+/// failing several times in a row.
+#[derive(Debug)]
+pub enum Error {
+    Config(ConfigError),
+    /// No input device, or the input stream could not be built or started
+    AudioInput(String),
+    /// The output device could not be opened, or the audio could not be decoded or written
+    AudioOutput(String),
+    /// The audio could not be converted to 16 kHz mono
+    Resample(String),
+    Whisper(whisper_rs::WhisperError),
+    /// The chat backend failed to start or broke off mid-stream
+    Chat(Box<dyn std::error::Error + Send + Sync>),
+    Http(reqwest::Error),
+    /// A text-to-speech API answered with an error status
+    Tts(String),
+    /// An audio file could not be opened or decoded
+    AudioFile(String),
+    Io(io::Error),
+    /// The user closed the input, e.g. with Ctrl-D
+    EndOfInput,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(err) => write!(f, "{}", err),
+            Error::AudioInput(msg) => write!(f, "audio input: {}", msg),
+            Error::AudioOutput(msg) => write!(f, "audio output: {}", msg),
+            Error::Whisper(err) => write!(f, "speech recognition failed: {:?}", err),
+            Error::Chat(err) => write!(f, "chat request failed: {}", err),
+            Error::Http(err) => write!(f, "request failed: {}", err),
+            Error::Tts(msg) => write!(f, "speech synthesis failed: {}", msg),
+            Error::Resample(msg) => write!(f, "resampling failed: {}", msg),
+            Error::AudioFile(msg) => write!(f, "could not read audio file: {}", msg),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::EndOfInput => write!(f, "end of input"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Error::Config(err)
+    }
+}
+
+impl From<whisper_rs::WhisperError> for Error {
+    fn from(err: whisper_rs::WhisperError) -> Self {
+        Error::Whisper(err)
+    }
+}
+
+impl From<async_openai::error::OpenAIError> for Error {
+    fn from(err: async_openai::error::OpenAIError) -> Self {
+        Error::Chat(Box::new(err))
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+impl From<symphonia::core::errors::Error> for Error {
+    fn from(err: symphonia::core::errors::Error) -> Self {
+        Error::AudioFile(err.to_string())
+    }
+}
+
+impl From<hound::Error> for Error {
+    fn from(err: hound::Error) -> Self {
+        Error::AudioOutput(err.to_string())
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::{stdout, Write};
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+
+use crate::error::{Error, Result};
+use crate::keys::{exit_on_ctrl_c, is_press, RawMode};
+use crate::stt_assistant::Stt;
+use crate::traits::{GetInput, Interruption};
+use crate::transcript::Transcript;
+
+const TOGGLE_KEY: KeyCode = KeyCode::Tab;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Voice,
+    Keyboard,
+}
+
+/// Voice input that can be switched to typing and back with Tab.
+///
+/// In voice mode, Tab pressed at any point before recording starts
+/// (e.g. while the assistant is still talking) switches to typing.
+/// In keyboard mode, Tab on an empty line switches back to voice.
+pub struct HybridInput {
+    voice: Stt,
+    mode: Mode,
+}
+
+impl HybridInput {
+    pub fn new(voice: Stt) -> Self {
+        Self {
+            voice,
+            mode: Mode::Voice,
+        }
+    }
+}
+
+impl GetInput for HybridInput {
+    fn record(&mut self) -> Result<Transcript> {
+        loop {
+            match self.mode {
+                Mode::Voice => {
+                    if toggle_pressed()? {
+                        self.mode = Mode::Keyboard;
+                        continue;
+                    }
+                    println!("(Tab to type instead)");
+                    let result = self.voice.record();
+                    if let Err(Error::AudioInput(_)) = result {
+                        // No microphone, so the user can still type
+                        println!("(keyboard)");
+                        self.mode = Mode::Keyboard;
+                    }
+                    return result;
+                }
+                Mode::Keyboard => match read_line_or_toggle()? {
+                    Some(line) => return Ok(Transcript::typed(line)),
+                    None => {
+                        println!("(voice)");
+                        self.mode = Mode::Voice;
+                    }
+                },
+            }
+        }
+    }
+
+    fn set_context(&mut self, recent: &[String]) {
+        self.voice.set_context(recent);
+    }
+
+    fn start_listening(&mut self) -> Result<()> {
+        match self.mode {
+            Mode::Voice => self.voice.start_listening(),
+            Mode::Keyboard => Ok(()),
+        }
+    }
+
+    fn interruption(&mut self, playback_level: f32) -> Result<Option<Interruption>> {
+        match self.mode {
+            Mode::Voice => self.voice.interruption(playback_level),
+            Mode::Keyboard => Ok(None),
+        }
+    }
+
+    fn started_over_reply(&self) -> bool {
+        self.mode == Mode::Voice && self.voice.started_over_reply()
+    }
+}
+
+/// Check the keys typed since the last turn for the toggle key.
+fn toggle_pressed() -> Result<bool> {
+    let _raw_mode = RawMode::enable()?;
+
+    let mut pressed = false;
+    while event::poll(Duration::ZERO).unwrap_or(false) {
+        if let Ok(Event::Key(key)) = event::read() {
+            exit_on_ctrl_c(&key);
+            pressed |= is_press(&key, TOGGLE_KEY);
+        }
+    }
+
+    Ok(pressed)
+}
+
+/// Read a line in raw mode, or `None` if the toggle key is pressed on an empty line.
+fn read_line_or_toggle() -> Result<Option<String>> {
+    let raw_mode = RawMode::enable()?;
+
+    let mut line = String::new();
+    let mut lock = stdout().lock();
+    let result = loop {
+        let key = match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            _ => continue,
+        };
+        exit_on_ctrl_c(&key);
+
+        match key.code {
+            TOGGLE_KEY if line.is_empty() => break None,
+            // Nothing to send yet
+            KeyCode::Enter if line.trim().is_empty() => {}
+            KeyCode::Enter => break Some(line.trim().to_string()),
+            KeyCode::Backspace if line.pop().is_some() => {
+                write!(lock, "\u{8} \u{8}")?;
+            }
+            KeyCode::Char(c) => {
+                line.push(c);
+                write!(lock, "{}", c)?;
+            }
+            _ => {}
+        }
+        lock.flush()?;
+    };
+
+    drop(raw_mode);
+    println!();
+    Ok(result)
+}
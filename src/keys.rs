@@ -0,0 +1,217 @@
+use std::io::stdout;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{execute, terminal};
+
+use crate::error::Result;
+
+pub const TALK_KEY: KeyCode = KeyCode::Char(' ');
+pub const CANCEL_KEY: KeyCode = KeyCode::Esc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    TalkPressed,
+    /// Only reported if `Hotkeys::can_hold` is true
+    TalkReleased,
+    Cancel,
+}
+
+/// Reads the recording hotkeys while audio is being captured.
+///
+/// Most terminals only report key presses. Where the terminal supports
+/// the kitty keyboard protocol, releases are reported too, so the talk
+/// key can be held down instead of pressed twice.
+pub struct Hotkeys {
+    can_hold: bool,
+    _raw_mode: RawMode,
+}
+
+impl Hotkeys {
+    /// Read key presses only.
+    pub fn enable() -> Result<Self> {
+        Ok(Self {
+            can_hold: false,
+            _raw_mode: RawMode::enable()?,
+        })
+    }
+
+    /// Also read key releases if the terminal can report them.
+    pub fn enable_with_release() -> Result<Self> {
+        // Has to be asked before raw mode is on
+        let can_hold = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        let raw_mode = RawMode::enable()?;
+        if can_hold {
+            execute!(
+                stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(Self {
+            can_hold,
+            _raw_mode: raw_mode,
+        })
+    }
+
+    pub fn can_hold(&self) -> bool {
+        self.can_hold
+    }
+
+    /// The next hotkey typed so far, ignoring other keys. Doesn't block.
+    pub fn poll(&self) -> Result<Option<Hotkey>> {
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                if let Some(hotkey) = hotkey(&key) {
+                    return Ok(Some(hotkey));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Block until a hotkey is typed.
+    pub fn wait(&self) -> Result<Hotkey> {
+        loop {
+            if let Event::Key(key) = event::read()? {
+                if let Some(hotkey) = hotkey(&key) {
+                    return Ok(hotkey);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Hotkeys {
+    fn drop(&mut self) {
+        if self.can_hold {
+            let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+        }
+    }
+}
+
+fn hotkey(key: &KeyEvent) -> Option<Hotkey> {
+    exit_on_ctrl_c(key);
+    match (key.code, key.kind) {
+        // Auto-repeat while the key is held is not a new press
+        (_, KeyEventKind::Repeat) => None,
+        (TALK_KEY, KeyEventKind::Press) => Some(Hotkey::TalkPressed),
+        (TALK_KEY, KeyEventKind::Release) => Some(Hotkey::TalkReleased),
+        (CANCEL_KEY, KeyEventKind::Press) => Some(Hotkey::Cancel),
+        _ => None,
+    }
+}
+
+/// Wait for Enter to accept or Esc to reject.
+pub fn confirm() -> Result<bool> {
+    let _raw_mode = RawMode::enable()?;
+    loop {
+        if let Event::Key(key) = event::read()? {
+            exit_on_ctrl_c(&key);
+            if is_press(&key, KeyCode::Enter) {
+                return Ok(true);
+            }
+            if is_press(&key, KeyCode::Esc) {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+/// Ctrl-C stops the reply being streamed, and quits at any other time.
+///
+/// Once installed, SIGINT no longer quits by itself for the rest of the
+/// process, so this must only be installed for the chat loop.
+#[derive(Clone, Default)]
+pub struct CtrlC {
+    replying: Arc<AtomicBool>,
+    pressed: Arc<AtomicBool>,
+}
+
+impl CtrlC {
+    pub fn install() -> Self {
+        let ctrl_c = Self::default();
+        let handler = ctrl_c.clone();
+        tokio::spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if !handler.replying.load(Ordering::SeqCst) {
+                    let _ = terminal::disable_raw_mode();
+                    std::process::exit(130);
+                }
+                handler.pressed.store(true, Ordering::SeqCst);
+            }
+        });
+        ctrl_c
+    }
+
+    pub fn start_reply(&self) {
+        self.pressed.store(false, Ordering::SeqCst);
+        self.replying.store(true, Ordering::SeqCst);
+    }
+
+    pub fn end_reply(&self) {
+        self.replying.store(false, Ordering::SeqCst);
+    }
+
+    /// Whether Ctrl-C was pressed since `start_reply`.
+    pub fn pressed(&self) -> bool {
+        self.pressed.load(Ordering::SeqCst)
+    }
+}
+
+/// Keeps the terminal in raw mode until dropped, so errors can't leave it raw.
+pub struct RawMode;
+
+impl RawMode {
+    pub fn enable() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+pub fn is_press(key: &KeyEvent, code: KeyCode) -> bool {
+    key.code == code && key.kind != KeyEventKind::Release
+}
+
+/// Raw mode swallows SIGINT, so Ctrl-C has to be handled by hand.
+pub fn exit_on_ctrl_c(key: &KeyEvent) {
+    if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+        let _ = terminal::disable_raw_mode();
+        std::process::exit(130);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hotkeys_ignore_repeats_and_other_keys() {
+        let key = |code, kind| KeyEvent::new_with_kind(code, KeyModifiers::NONE, kind);
+
+        assert_eq!(
+            hotkey(&key(TALK_KEY, KeyEventKind::Press)),
+            Some(Hotkey::TalkPressed)
+        );
+        assert_eq!(
+            hotkey(&key(TALK_KEY, KeyEventKind::Release)),
+            Some(Hotkey::TalkReleased)
+        );
+        assert_eq!(hotkey(&key(TALK_KEY, KeyEventKind::Repeat)), None);
+        assert_eq!(
+            hotkey(&key(CANCEL_KEY, KeyEventKind::Press)),
+            Some(Hotkey::Cancel)
+        );
+        assert_eq!(hotkey(&key(KeyCode::Tab, KeyEventKind::Press)), None);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::transcript::Transcript;
+
+// Only the first few words can be the keyword, e.g. "Hey Jarvy, ..."
+const MAX_LEADING_WORDS: usize = 3;
+
+/// Fuzzy matching of a word said at the start of an utterance, like the
+/// wake word or "stop", in what Whisper heard.
+///
+/// Whisper spells a made-up name differently from one run to the next
+/// ("Jarvy", "Jarvis", "Jervy"). A word one edit away from a keyword of five
+/// letters or more counts, and so does one two edits away if only its ending
+/// differs, which keeps out words like "Java". Shorter keywords like "stop"
+/// must be said exactly, or "top" and "shop" would match.
+pub struct Keyword {
+    word: String,
+    // All but the last two letters
+    stem: String,
+}
+
+impl Keyword {
+    pub fn new(word: &str) -> Self {
+        let word = normalize(word);
+        let len = word.chars().count();
+        let stem = word.chars().take(len.saturating_sub(2)).collect();
+        Self { word, stem }
+    }
+
+    fn matches(&self, word: &str) -> bool {
+        match edit_distance(word, &self.word) {
+            0 => true,
+            // Short words are too easily confused
+            1 => self.stem.len() >= 3,
+            2 => self.stem.len() >= 3 && word.starts_with(&self.stem),
+            _ => false,
+        }
+    }
+
+    /// The byte offset in `text` right after the keyword, if `text` starts with it.
+    pub fn find(&self, text: &str) -> Option<usize> {
+        let mut words = 0;
+        let mut offset = 0;
+        for word in text.split_inclusive(char::is_whitespace) {
+            offset += word.len();
+            let normalized = normalize(word);
+            if normalized.is_empty() {
+                continue;
+            }
+            if self.matches(&normalized) {
+                return Some(offset);
+            }
+            words += 1;
+            if words == MAX_LEADING_WORDS {
+                break;
+            }
+        }
+        None
+    }
+
+    /// Whether `text` is just the keyword, maybe after a word like "okay".
+    pub fn is_whole(&self, text: &str) -> bool {
+        self.find(text)
+            .is_some_and(|end| !text[end..].contains(char::is_alphanumeric))
+    }
+
+    /// Remove the keyword and anything before it from the start of `transcript`.
+    pub fn strip(&self, transcript: &mut Transcript) {
+        let Some(first) = transcript.segments.first_mut() else {
+            return;
+        };
+        if let Some(end) = self.find(&first.text) {
+            first.text = first.text[end..]
+                .trim_start_matches(|c: char| c.is_whitespace() || c.is_ascii_punctuation())
+                .to_string();
+        }
+        transcript
+            .segments
+            .retain(|segment| !segment.text.trim().is_empty());
+    }
+}
+
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_wake_word() {
+        let wake_word = Keyword::new("Jarvy");
+        assert_eq!(wake_word.find("Jarvy"), Some(5));
+        assert_eq!(wake_word.find("Hey Jarvis, open main.rs"), Some(12));
+        assert_eq!(wake_word.find("jervy."), Some(6));
+        assert_eq!(wake_word.find("We are very close"), None);
+        assert_eq!(wake_word.find("Java is slow"), None);
+        assert_eq!(wake_word.find("I told you about Jarvy"), None);
+    }
+
+    #[test]
+    fn test_strip_wake_word() {
+        let wake_word = Keyword::new("Jarvy");
+        let mut transcript = Transcript::typed("Hey Jarvy, open main.rs".into());
+        wake_word.strip(&mut transcript);
+        assert_eq!(transcript.text(), "open main.rs");
+
+        let mut transcript = Transcript::typed("Jarvy.".into());
+        wake_word.strip(&mut transcript);
+        assert!(transcript.segments.is_empty());
+    }
+
+    #[test]
+    fn test_whole_stop_word() {
+        let stop_word = Keyword::new("stop");
+        assert!(stop_word.is_whole("Stop."));
+        assert!(stop_word.is_whole("Okay, stop!"));
+        assert!(!stop_word.is_whole("Stop using unwrap here"));
+        for near_miss in ["Top.", "Shop", "Step.", "Stops"] {
+            assert_eq!(stop_word.find(near_miss), None, "{}", near_miss);
+        }
+        assert!(!Keyword::new("").is_whole("Stop."));
+    }
+}
@@ -1,26 +1,63 @@
 #![deny(clippy::if_same_then_else)]
 
+mod audio_input;
+mod audio_source;
+mod barge_in;
+mod chat_backend;
+mod cli;
 mod code_assistant;
+mod config;
+mod error;
+mod hybrid_input;
+mod keys;
+mod keyword;
+mod markdown;
+mod sentences;
+mod session;
+mod speaker;
+mod speech_engine;
+mod speech_recorder;
+mod speech_text;
 mod stt_assistant;
 mod traits;
-mod tts_assistant;
-mod tts_assistant2;
+mod transcript;
 mod tty_input;
+mod vad;
+mod vocabulary;
 
 use async_openai::types::ChatCompletionRequestMessage;
-use async_openai::types::CreateChatCompletionRequestArgs;
 use async_openai::types::Role;
+use audio_source::{FileSource, PcmSource};
+use chat_backend::{LocalBackend, OpenAiBackend};
+use clap::Parser;
+use cli::{Cli, Command, InputSource};
 use code_assistant::CodeAssistant;
+use config::{Config, ConfigError, LlmBackendKind};
+use error::Error;
 use futures::StreamExt;
-use tts_assistant::TtsAssistant;
-use tts_assistant2::TtsAssistant2;
+use hybrid_input::HybridInput;
+use keys::CtrlC;
+use keyword::Keyword;
+use markdown::{Event, MarkdownStream};
+use speaker::Speaker;
+use stt_assistant::{Stt, Transcriber};
+use transcript::Verdict;
+use tty_input::TtyInput;
 
-use std::env;
-use std::error::Error;
 use std::io::{stdout, Write};
 use std::path::Path;
+use std::time::Duration;
 
-use crate::traits::GetInput;
+use crate::traits::{ChatBackend, GetInput, Interruption};
+
+// How many messages the Whisper prompt is primed with
+const RECENT_MESSAGES: usize = 4;
+// How often Ctrl-C and the microphone are checked for the user interrupting the reply
+const INTERRUPTION_POLL: Duration = Duration::from_millis(50);
+// Appended to a reply that was cut short, so the model knows on the next turn
+const INTERRUPTED_MARKER: &str = "[interrupted by the user]";
+// Input errors in a row that end the session, as retrying won't help
+const MAX_INPUT_FAILURES: usize = 3;
 
 macro_rules! char_vec {
     ($s:expr) => {{
@@ -29,128 +66,218 @@ macro_rules! char_vec {
 }
 
 async fn perform_request_with_streaming(
+    backend: &dyn ChatBackend,
     chat_history: Vec<ChatCompletionRequestMessage>,
-    speech_assistant: &mut TtsAssistant2,
+    speech_assistant: &mut Speaker,
     code_assistant: &mut CodeAssistant,
-) -> ChatCompletionRequestMessage {
+    input: &mut dyn GetInput,
+    ctrl_c: &CtrlC,
+) -> error::Result<ChatCompletionRequestMessage> {
     // To save the current reply
     let mut current_reply: Vec<String> = Vec::new();
 
-    // Set up the request
-    let request = CreateChatCompletionRequestArgs::default()
-        .model("gpt-3.5-turbo")
-        .messages(chat_history)
-        .stream(true)
-        .build()
-        .unwrap();
-
     // Make the request
-    let client = async_openai::Client::new();
-    let mut response = client.chat().create_stream(request).await.unwrap();
+    let mut response = backend.stream_chat(chat_history);
 
     // Acquite the stdout lock to print the assistant's response
     let mut lock = stdout().lock();
 
-    let state = State::Prose;
+    // Tells prose, which is read out, from code blocks, which are saved
+    let mut markdown = MarkdownStream::default();
 
-    let mut code_buffer = vec![];
-    let mut speech_buffer: Vec<char> = vec![];
-    let mut tmp_buffer = vec![];
+    // Listen for the user interrupting the reply while it streams and plays
+    report(input.start_listening());
+    let mut interruption_poll = tokio::time::interval(INTERRUPTION_POLL);
+    let mut interrupted = None;
 
     // Process the stream
-    while let Some(result) = response.next().await {
-        let mut response = result.expect("Error while reading response");
-        let something = response.choices.pop().unwrap();
-
-        if let Some(ref token) = something.delta.content {
-            // Display the token
-            write!(lock, "{}", token).unwrap();
-            stdout().flush().unwrap();
-
-            // Add the token to the current reply
-            current_reply.push(token.clone());
-
-            // Process token according to state
-            let (state, event) = transition(state, token, &code_buffer);
-            match (state, event) {
-                (State::Code, Event::Append) => {
-                    code_assistant.push(&char_vec!(token));
-                    tmp_buffer.clear();
+    loop {
+        let token = tokio::select! {
+            result = response.next() => match result {
+                Some(Ok(token)) => token,
+                Some(Err(err)) => {
+                    // Nothing of a broken reply carries over to the next one
+                    speech_assistant.stop();
+                    code_assistant.discard();
+                    return Err(err);
                 }
-                (State::Code, Event::Flush) => {
-                    code_assistant.push(&char_vec!(token));
-                    code_assistant.flush();
-                    code_buffer.clear();
+                None => break,
+            },
+            _ = interruption_poll.tick() => {
+                interrupted = interruption(input, speech_assistant, ctrl_c);
+                if interrupted.is_some() {
+                    break;
                 }
-                (State::Code, _) => {}
+                continue;
+            }
+        };
 
-                (State::Prose, Event::Append) => {
-                    speech_assistant.push(&char_vec!(token));
-                    tmp_buffer.clear();
-                }
-                (State::Prose, Event::Flush) => {
-                    speech_assistant.push(&char_vec!(token));
-                    speech_assistant.flush().await;
-                    speech_buffer.clear();
-                }
-                (State::Prose, _) => {
-                    speech_assistant.push(&char_vec!(token));
-                }
+        // Display the token
+        write!(lock, "{}", token)?;
+        stdout().flush()?;
 
-                (State::MaybeCode, Event::AppendTmp) => {
-                    tmp_buffer.extend(token.chars());
-                }
-                _ => {}
-            }
+        // Add the token to the current reply
+        current_reply.push(token.clone());
+
+        for event in markdown.push(&token) {
+            route(event, speech_assistant, code_assistant);
+        }
+    }
+
+    if interrupted.is_none() {
+        // Flush any remaining buffer
+        for event in markdown.finish() {
+            route(event, speech_assistant, code_assistant);
+        }
+        // A block the reply left open is still saved
+        report(code_assistant.close());
+        report(speech_assistant.flush());
+
+        while interrupted.is_none() && speech_assistant.is_playing() {
+            interruption_poll.tick().await;
+            interrupted = interruption(input, speech_assistant, ctrl_c);
         }
     }
 
-    // Flush any remaining buffer
-    code_assistant.flush();
-    speech_assistant.flush().await;
+    let mut content = current_reply.join("");
+    if let Some(interruption) = interrupted {
+        // Dropping the stream cancels the request. Code blocks that were
+        // complete have been written, the one cut off is dropped.
+        drop(response);
+        speech_assistant.stop();
+        code_assistant.discard();
+        content = mark_interrupted(content);
+        match interruption {
+            Interruption::Stop => writeln!(lock, "\n(stopped)")?,
+            Interruption::BargeIn => writeln!(lock, "\n(interrupted)")?,
+        }
+    }
 
     // Append the current reply to the chat history and clear the current reply
-    ChatCompletionRequestMessage {
+    Ok(ChatCompletionRequestMessage {
         role: Role::Assistant,
-        content: current_reply.join(""),
+        content,
         name: None,
+    })
+}
+
+/// Read prose out and save code blocks.
+fn route(event: Event, speech_assistant: &mut Speaker, code_assistant: &mut CodeAssistant) {
+    match event {
+        Event::Prose(text) => {
+            report(speech_assistant.push(&char_vec!(text)));
+        }
+        // Keep the backticks so it is read as code
+        Event::InlineCode(code) => {
+            report(speech_assistant.push(&char_vec!(format!("`{}`", code))));
+        }
+        Event::FenceOpen { info } => code_assistant.open(&info),
+        Event::CodeLine(line) => code_assistant.push_line(&line),
+        Event::FenceClose => {
+            report(code_assistant.close());
+        }
+    }
+}
+
+/// How the user interrupted the reply, if they did.
+fn interruption(
+    input: &mut dyn GetInput,
+    speech_assistant: &Speaker,
+    ctrl_c: &CtrlC,
+) -> Option<Interruption> {
+    if ctrl_c.pressed() {
+        return Some(Interruption::Stop);
     }
+    report(input.interruption(speech_assistant.playback_level())).flatten()
 }
 
-async fn chat() {
-    // Environment
-    let args: Vec<String> = env::args().collect();
-    let home_dir = Path::new(&args[1]).to_path_buf();
+/// Close a code block the reply was cut off in, and say it was cut off.
+fn mark_interrupted(mut content: String) -> String {
+    let mut markdown = MarkdownStream::default();
+    markdown.push(&content);
+    if let Some(fence) = markdown.open_fence() {
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&fence);
+    }
+    format!("{}\n\n{}", content, INTERRUPTED_MARKER)
+}
 
+async fn chat(config: Config, mut input: Box<dyn GetInput>) -> error::Result<()> {
     // Initial intent
     let mut chat_history: Vec<_> = vec![ChatCompletionRequestMessage {
         role: Role::System,
-        content: r#"You are going to be pair-programme with me. I need you to be less verbose in your explanations. 
-    
-        I need you to provide me at most one code block. 
-            
-        Please specify the language and the filename of the code block at the backticks
-        
-        ```<language>-<filename>
-        <code> 
-        ```.
-        
-            Most of the time we'll be working with one file at a time, represented by a code block. Changes to a code block should be rewritten entirely. Any suggestions or questions you have, please ask me. I'll be happy to answer them. Let's get started!"#.into(),
+        content: config.llm.system_prompt.clone(),
         name: None,
     }];
 
+    // Chat backend
+    let backend: Box<dyn ChatBackend> = match config.llm.backend {
+        LlmBackendKind::OpenAi => Box::new(OpenAiBackend::new(&config.llm.model)),
+        LlmBackendKind::Local => Box::new(LocalBackend::new(
+            config.llm.api_base.clone().unwrap_or_default(),
+            &config.llm.model,
+        )),
+    };
+
     // Assistants
-    let mut speech_assistant = TtsAssistant2::default();
-    let mut code_assistant = CodeAssistant::new(home_dir);
-    let mut stt =
-        stt_assistant::Stt::new("/Users/raimibinkarim/Desktop/ggml-tiny.en.bin".to_string());
+    let home_dir = config.code.home_dir.clone().unwrap_or_default();
+    let mut speech_assistant = Speaker::new(&config.tts)?;
+    let mut code_assistant = CodeAssistant::new(home_dir.clone())?;
+
+    // Session log
+    let session_path = session::new_session_path(&home_dir);
+    println!("Saving session to {}", session_path.display());
+    if let Some(dir) = speech_assistant.recording_dir() {
+        println!("Saving speech to {}", dir.display());
+    }
+
+    let ctrl_c = CtrlC::install();
+    let stop_word = Keyword::new(&config.stt.stop_word);
+    let mut input_failures = 0;
 
     // Turn-based
     loop {
         // User
         println!("\nYou: ");
-        let text = stt.record();
-        println!("{}", text);
+        input.set_context(&recent_prose(&chat_history));
+        // Recording blocks, so let the runtime move its other tasks off this thread
+        let transcript = match tokio::task::block_in_place(|| input.record()) {
+            Ok(transcript) => transcript,
+            Err(Error::EndOfInput) => return Ok(()),
+            Err(err) => {
+                input_failures += 1;
+                if input_failures >= MAX_INPUT_FAILURES {
+                    return Err(err);
+                }
+                report::<()>(Err(err));
+                continue;
+            }
+        };
+        input_failures = 0;
+        let text = transcript.text();
+        if input.started_over_reply() && stop_word.is_whole(&text) {
+            // Said over the reply to stop it, not meant for the model
+            println!("(stopped)");
+            continue;
+        }
+        match transcript.verdict(config.stt.min_confidence, config.stt.max_no_speech_prob) {
+            Verdict::Accept => println!("{}", text),
+            Verdict::Repeat => {
+                println!("Sorry, I didn't catch that. Please say it again.");
+                continue;
+            }
+            Verdict::Confirm => {
+                println!(
+                    "{}\n(not sure I heard that right: Enter to send, Esc to say it again)",
+                    text
+                );
+                if !report(keys::confirm()).unwrap_or(false) {
+                    continue;
+                }
+            }
+        }
         let prompt = ChatCompletionRequestMessage {
             role: Role::User,
             content: text,
@@ -160,109 +287,250 @@ async fn chat() {
 
         // Assistant
         print!("\nAssistant: ");
+        ctrl_c.start_reply();
         let reply = perform_request_with_streaming(
+            backend.as_ref(),
             chat_history.clone(),
             &mut speech_assistant,
             &mut code_assistant,
+            input.as_mut(),
+            &ctrl_c,
         )
         .await;
-        chat_history.push(reply);
+        ctrl_c.end_reply();
+        speech_assistant.end_turn();
+        // Asked now rather than mid-reply, so Ctrl-C quits while waiting for an answer
+        report(tokio::task::block_in_place(|| code_assistant.ask()));
+        match reply {
+            Ok(reply) => chat_history.push(reply),
+            Err(err) => {
+                // Drop the unanswered prompt so the user can try again
+                report::<()>(Err(err));
+                chat_history.pop();
+                continue;
+            }
+        }
+
+        if let Err(err) = session::save(&session_path, &chat_history) {
+            eprintln!("Could not save session: {}", err);
+        }
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    chat().await;
+/// Print a saved session and read the assistant's prose out loud.
+async fn replay(config: Config, session_path: &Path) -> error::Result<()> {
+    let chat_history = session::load(session_path)?;
+    let mut speech_assistant = Speaker::new(&config.tts)?;
+
+    for message in chat_history {
+        match message.role {
+            Role::User => println!("\nYou: \n{}", message.content),
+            Role::Assistant => {
+                println!("\nAssistant: {}", message.content);
+
+                report(speech_assistant.push(&char_vec!(prose(&message.content))));
+                report(speech_assistant.flush());
+                speech_assistant.end_turn();
+                speech_assistant.finish().await;
+            }
+            Role::System => {}
+        }
+    }
 
     Ok(())
 }
 
-fn transition(from: State, token: &str, code_buffer: &[char]) -> (State, Event) {
-    match (from, token) {
-        (State::Code, token) => {
-            let token_backticks = token.chars().filter(|&x| x == '`').count();
-            let num_backticks = code_buffer.iter().filter(|&x| x == &'`').count();
-            if num_backticks + token_backticks == 6 {
-                (State::Prose, Event::Flush)
-            } else {
-                (State::Code, Event::Append)
-            }
+/// The text of a message without its code blocks.
+fn prose(content: &str) -> String {
+    let mut markdown = MarkdownStream::default();
+    let mut events = markdown.push(content.trim_end_matches(INTERRUPTED_MARKER));
+    events.extend(markdown.finish());
+    let mut prose = String::new();
+    for event in events {
+        match event {
+            Event::Prose(text) => prose.push_str(&text),
+            // Keep the backticks so it is read as code
+            Event::InlineCode(code) => prose.push_str(&format!("`{}`", code)),
+            Event::FenceClose => prose.push('\n'),
+            _ => {}
         }
+    }
+    prose
+}
 
-        (State::MaybeCode, "`" | "``") => (State::Code, Event::Append),
-        (State::MaybeCode, _) => (State::Prose, Event::Append),
+/// The prose of the last few user and assistant messages, oldest first.
+fn recent_prose(chat_history: &[ChatCompletionRequestMessage]) -> Vec<String> {
+    let mut recent = chat_history
+        .iter()
+        .rev()
+        .filter(|message| !matches!(message.role, Role::System))
+        .take(RECENT_MESSAGES)
+        .map(|message| prose(&message.content))
+        .collect::<Vec<_>>();
+    recent.reverse();
+    recent
+}
 
-        (State::Prose, "`") => (State::MaybeCode, Event::AppendTmp),
-        (State::Prose, "``" | "```") => (State::Code, Event::Append),
-        (State::Prose, ".") => (State::Prose, Event::Flush),
-        (State::Prose, _) => (State::Prose, Event::Append),
-    }
+/// Print an error that should not end the session.
+fn report<T>(result: error::Result<T>) -> Option<T> {
+    result.map_err(|err| eprintln!("\nError: {}", err)).ok()
 }
 
-#[derive(PartialEq, Debug)]
-enum Event {
-    Flush,
-    Append,
-    AppendTmp,
+/// Exit with a readable message on configuration errors.
+fn check<T>(result: Result<T, ConfigError>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    let mut config = check(Config::load());
+    cli.apply(&mut config);
+
+    match cli.command {
+        Command::Chat | Command::Text => {
+            check(config.validate());
+            let input = create_input(cli.input_source(), &config);
+            chat(config, input?).await?;
+        }
+        Command::Transcribe {
+            ref path,
+            format,
+            sample_rate,
+            channels,
+            timestamps,
+        } => {
+            check(config.validate_stt());
+            // No microphone needed, so this also works on machines without a sound card
+            let mut transcriber = Transcriber::new(&config.stt)?;
+            let transcript = if path.as_os_str() == "-" {
+                let mut source =
+                    PcmSource::new(std::io::stdin().lock(), format, sample_rate, channels);
+                transcriber.transcribe_source(&mut source)?
+            } else {
+                transcriber.transcribe_source(&mut FileSource::open(path)?)?
+            };
+            if timestamps {
+                for segment in &transcript.segments {
+                    println!(
+                        "[{:>7.2}s -> {:>7.2}s] {}",
+                        segment.start.as_secs_f32(),
+                        segment.end.as_secs_f32(),
+                        segment.text
+                    );
+                }
+            } else {
+                println!("{}", transcript.text());
+            }
+        }
+        Command::Say { ref text } => {
+            check(config.validate_tts());
+            let mut speech_assistant = Speaker::new(&config.tts)?;
+            speech_assistant.push(&char_vec!(text))?;
+            speech_assistant.flush()?;
+            speech_assistant.finish().await;
+        }
+        Command::Replay { ref session } => {
+            check(config.validate_tts());
+            replay(config, session).await?;
+        }
+        Command::Devices => {
+            for name in audio_input::list_input_devices()? {
+                println!("{}", name);
+            }
+        }
+    }
+
+    Ok(())
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
-enum State {
-    Code,
-    MaybeCode,
-    Prose,
+/// Only touch the microphone and Whisper model if the input needs them.
+fn create_input(source: InputSource, config: &Config) -> error::Result<Box<dyn GetInput>> {
+    Ok(match source {
+        InputSource::Keyboard => Box::new(TtyInput),
+        InputSource::Voice => {
+            check(config.validate_stt());
+            Box::new(Stt::new(&config.stt, config.code.home_dir.clone())?)
+        }
+        InputSource::Hybrid => {
+            check(config.validate_stt());
+            let voice = Stt::new(&config.stt, config.code.home_dir.clone())?;
+            Box::new(HybridInput::new(voice))
+        }
+    })
 }
 
-// transition tests
 #[cfg(test)]
 mod tests {
+    use futures::stream;
+
     use super::*;
+    use crate::chat_backend::TokenStream;
+    use crate::speech_engine::SilentEngine;
 
-    #[test]
-    fn test_from_code_transition() {
-        assert_eq!(
-            transition(State::Code, "`", &['`']),
-            (State::Code, Event::Append)
-        );
-        assert_eq!(
-            transition(State::Code, "``", &['`']),
-            (State::Code, Event::Append)
-        );
-        assert_eq!(
-            transition(State::Code, "`", char_vec!("```code``").as_slice()),
-            (State::Prose, Event::Flush)
-        );
-        assert_eq!(
-            transition(State::Code, "``", char_vec!("```code`").as_slice()),
-            (State::Prose, Event::Flush)
-        );
+    /// A backend that replies with the same tokens every time.
+    struct ScriptedBackend {
+        tokens: Vec<&'static str>,
     }
 
-    #[test]
-    fn test_from_maybe_transition() {
-        assert_eq!(
-            transition(State::MaybeCode, "`", &['`']),
-            (State::Code, Event::Append)
-        );
-        assert_eq!(
-            transition(State::MaybeCode, "``", &['`']),
-            (State::Code, Event::Append)
-        );
-        assert_eq!(
-            transition(State::MaybeCode, "a", &['`']),
-            (State::Prose, Event::Append)
-        );
+    impl ChatBackend for ScriptedBackend {
+        fn stream_chat(&self, _messages: Vec<ChatCompletionRequestMessage>) -> TokenStream {
+            let tokens = self.tokens.clone();
+            Box::pin(stream::iter(tokens).map(|token| Ok(token.to_string())))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reply_is_streamed_to_speech_and_code() {
+        let dir = std::env::temp_dir().join(format!("jarvy-reply-{}", std::process::id()));
+        let backend = ScriptedBackend {
+            tokens: vec![
+                "Here",
+                " it is:\n``",
+                "`rust-src/main.rs\nfn main",
+                "() {}\n```\n~~~toml-Cargo.toml\n",
+                "[package]\n~~~\nRun it.",
+            ],
+        };
+        let mut speaker = Speaker::with_engine(Box::new(SilentEngine), None);
+        let mut code_assistant = CodeAssistant::new(dir.clone()).unwrap();
+
+        let reply = perform_request_with_streaming(
+            &backend,
+            vec![],
+            &mut speaker,
+            &mut code_assistant,
+            &mut TtyInput,
+            &CtrlC::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(reply.content, backend.tokens.concat());
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("src/main.rs"), "fn main() {}\n");
+        assert_eq!(read("Cargo.toml"), "[package]\n");
+        std::fs::remove_dir_all(dir).unwrap();
     }
 
     #[test]
-    fn test_prose_transition() {
-        assert_eq!(
-            transition(State::Prose, "`", &[]),
-            (State::MaybeCode, Event::AppendTmp)
-        );
+    fn test_interrupted_reply_closes_code_block() {
+        let content = mark_interrupted("Sure:\n```rust-main.rs\nfn main() {".to_string());
         assert_eq!(
-            transition(State::Prose, "`", &['a']),
-            (State::MaybeCode, Event::AppendTmp)
+            content,
+            "Sure:\n```rust-main.rs\nfn main() {\n```\n\n[interrupted by the user]"
         );
+        assert_eq!(prose(&content).trim(), "Sure:");
+
+        // Tilde and longer fences, with backticks inside that don't count
+        let content = mark_interrupted("Run ```ls```:\n~~~sh\necho ```".to_string());
+        assert!(content.starts_with("Run ```ls```:\n~~~sh\necho ```\n~~~\n"));
+        assert_eq!(prose(&content).trim(), "Run `ls`:");
+        let content = mark_interrupted("````md\n```rust\n".to_string());
+        assert!(content.starts_with("````md\n```rust\n````\n"));
     }
 }
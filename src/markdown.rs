@@ -0,0 +1,389 @@
+use std::mem;
+
+/// What a reply is made of, as its tokens stream in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Text outside code, in as many pieces as it arrives in
+    Prose(String),
+    /// `code` within prose
+    InlineCode(String),
+    /// A fence opening a code block, with the info string after it, e.g. `rust-main.rs`
+    FenceOpen {
+        info: String,
+    },
+    /// A line of a code block, without its newline
+    CodeLine(String),
+    FenceClose,
+}
+
+/// A line of at least three backticks or tildes, indented by at most three spaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fence {
+    mark: char,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// The indentation and fence marks a prose line starts with, in `pending`
+    LineStart,
+    /// The rest of a fence line, in `pending`
+    FenceInfo(Fence),
+    Prose,
+    /// A run of backticks in prose, which may open inline code
+    Backticks(usize),
+    /// Inline code opened by `ticks` backticks, in `pending`, which ends with
+    /// `closing` backticks
+    InlineCode {
+        ticks: usize,
+        closing: usize,
+    },
+    /// A code block, with the current line in `pending`
+    Code(Fence),
+}
+
+/// Splits a streamed reply into prose and code, however its tokens are cut.
+///
+/// Fences follow CommonMark: three or more backticks or tildes opening a
+/// line, closed by a line of at least as many of the same mark. A shorter
+/// fence inside a block is part of the code, and backticks within a line
+/// are inline code.
+pub struct MarkdownStream {
+    state: State,
+    pending: String,
+    // Prose not returned yet
+    prose: String,
+    events: Vec<Event>,
+}
+
+impl Default for MarkdownStream {
+    fn default() -> Self {
+        Self {
+            state: State::LineStart,
+            pending: String::new(),
+            prose: String::new(),
+            events: vec![],
+        }
+    }
+}
+
+impl MarkdownStream {
+    /// Add the next part of the reply and return what it completes.
+    pub fn push(&mut self, text: &str) -> Vec<Event> {
+        for c in text.chars() {
+            self.feed(c);
+        }
+        self.take_events()
+    }
+
+    /// The fence that would close the code block the reply is in so far, if any.
+    pub fn open_fence(&self) -> Option<String> {
+        let fence = match self.state {
+            State::Code(fence) => Some(fence),
+            State::FenceInfo(fence) => info(&self.pending, fence).map(|_| fence),
+            State::LineStart => fence(&self.pending),
+            _ => None,
+        }?;
+        Some(fence.mark.to_string().repeat(fence.len))
+    }
+
+    /// The end of the reply: return what is still pending.
+    pub fn finish(&mut self) -> Vec<Event> {
+        if self.state == State::LineStart {
+            if let Some(fence) = fence(&self.pending) {
+                self.state = State::FenceInfo(fence);
+            }
+        }
+        match self.state {
+            State::FenceInfo(fence) => match info(&self.pending, fence) {
+                Some(info) => {
+                    self.pending.clear();
+                    self.emit(Event::FenceOpen { info });
+                }
+                None => self.reread_as_prose(),
+            },
+            State::LineStart => self.reread_as_prose(),
+            _ => {}
+        }
+
+        let pending = mem::take(&mut self.pending);
+        match self.state {
+            State::Backticks(ticks) => self.prose.push_str(&"`".repeat(ticks)),
+            State::InlineCode { ticks, closing } if closing == ticks => {
+                let code = pending[..pending.len() - ticks].to_string();
+                self.emit(Event::InlineCode(code));
+            }
+            State::InlineCode { ticks, .. } => {
+                self.prose.push_str(&"`".repeat(ticks));
+                self.prose.push_str(&pending);
+            }
+            State::Code(fence) if !pending.is_empty() => {
+                if is_closing(&pending, fence) {
+                    self.emit(Event::FenceClose);
+                } else {
+                    self.emit(Event::CodeLine(pending));
+                }
+            }
+            _ => {}
+        }
+        self.state = State::LineStart;
+        self.take_events()
+    }
+
+    fn feed(&mut self, c: char) {
+        match self.state {
+            State::LineStart => {
+                let marks = self.pending.trim_start_matches(' ');
+                let indent = self.pending.len() - marks.len();
+                let continues = match marks.chars().next() {
+                    None => (c == ' ' && indent < 3) || c == '`' || c == '~',
+                    Some(mark) => c == mark,
+                };
+                if continues {
+                    self.pending.push(c);
+                } else if let Some(fence) = fence(&self.pending) {
+                    self.state = State::FenceInfo(fence);
+                    self.feed(c);
+                } else {
+                    self.reread_as_prose();
+                    self.feed(c);
+                }
+            }
+            State::FenceInfo(fence) if c == '\n' => match info(&self.pending, fence) {
+                Some(info) => {
+                    self.pending.clear();
+                    self.state = State::Code(fence);
+                    self.emit(Event::FenceOpen { info });
+                }
+                None => {
+                    self.reread_as_prose();
+                    self.feed(c);
+                }
+            },
+            State::FenceInfo(_) => self.pending.push(c),
+            State::Prose => match c {
+                '`' => self.state = State::Backticks(1),
+                '\n' => {
+                    self.prose.push(c);
+                    self.state = State::LineStart;
+                }
+                _ => self.prose.push(c),
+            },
+            State::Backticks(ticks) if c == '`' => self.state = State::Backticks(ticks + 1),
+            State::Backticks(ticks) => {
+                self.state = State::InlineCode { ticks, closing: 0 };
+                self.feed(c);
+            }
+            State::InlineCode { ticks, closing } => {
+                if c == '`' {
+                    self.pending.push(c);
+                    self.state = State::InlineCode {
+                        ticks,
+                        closing: closing + 1,
+                    };
+                } else if closing == ticks {
+                    let mut code = mem::take(&mut self.pending);
+                    code.truncate(code.len() - ticks);
+                    self.state = State::Prose;
+                    self.emit(Event::InlineCode(code));
+                    self.feed(c);
+                } else if c == '\n' {
+                    // Not closed on its line, so the backticks were just text
+                    self.prose.push_str(&"`".repeat(ticks));
+                    self.prose.push_str(&mem::take(&mut self.pending));
+                    self.state = State::Prose;
+                    self.feed(c);
+                } else {
+                    self.pending.push(c);
+                    self.state = State::InlineCode { ticks, closing: 0 };
+                }
+            }
+            State::Code(fence) if c == '\n' => {
+                let line = mem::take(&mut self.pending);
+                if is_closing(&line, fence) {
+                    self.state = State::LineStart;
+                    self.emit(Event::FenceClose);
+                } else {
+                    self.emit(Event::CodeLine(line));
+                }
+            }
+            State::Code(_) => self.pending.push(c),
+        }
+    }
+
+    /// The line so far isn't a fence after all.
+    fn reread_as_prose(&mut self) {
+        self.state = State::Prose;
+        for c in mem::take(&mut self.pending).chars() {
+            self.feed(c);
+        }
+    }
+
+    fn emit(&mut self, event: Event) {
+        if !self.prose.is_empty() {
+            self.events.push(Event::Prose(mem::take(&mut self.prose)));
+        }
+        self.events.push(event);
+    }
+
+    fn take_events(&mut self) -> Vec<Event> {
+        if !self.prose.is_empty() {
+            self.events.push(Event::Prose(mem::take(&mut self.prose)));
+        }
+        mem::take(&mut self.events)
+    }
+}
+
+/// The fence `line` starts with, if it is one.
+fn fence(line: &str) -> Option<Fence> {
+    let marks = line.trim_start_matches(' ');
+    let mark = marks.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let len = marks.len() - marks.trim_start_matches(mark).len();
+    (line.len() - marks.len() <= 3 && len >= 3).then_some(Fence { mark, len })
+}
+
+/// The info string of a fence line, unless backticks in it make it inline code.
+fn info(line: &str, fence: Fence) -> Option<String> {
+    let info = line
+        .trim_start_matches(' ')
+        .trim_start_matches(fence.mark)
+        .trim();
+    (fence.mark != '`' || !info.contains('`')).then(|| info.to_string())
+}
+
+fn is_closing(line: &str, fence: Fence) -> bool {
+    let marks = line.trim_start_matches(' ');
+    let rest = marks.trim_start_matches(fence.mark);
+    line.len() - marks.len() <= 3 && marks.len() - rest.len() >= fence.len && rest.trim().is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn parse(chunks: &[&str]) -> Vec<Event> {
+        let mut stream = MarkdownStream::default();
+        let mut events: Vec<Event> = chunks.iter().flat_map(|chunk| stream.push(chunk)).collect();
+        events.extend(stream.finish());
+        merge_prose(events)
+    }
+
+    fn merge_prose(events: Vec<Event>) -> Vec<Event> {
+        let mut merged: Vec<Event> = vec![];
+        for event in events {
+            match (merged.last_mut(), event) {
+                (Some(Event::Prose(text)), Event::Prose(more)) => text.push_str(&more),
+                (_, event) => merged.push(event),
+            }
+        }
+        merged
+    }
+
+    fn prose(text: &str) -> Event {
+        Event::Prose(text.to_string())
+    }
+
+    fn code(line: &str) -> Event {
+        Event::CodeLine(line.to_string())
+    }
+
+    #[test]
+    fn test_fences_split_across_tokens() {
+        assert_eq!(
+            parse(&[
+                "Here:\n",
+                "``",
+                "`python",
+                "-main.py\nprint(1)\n",
+                "``",
+                "`\n",
+                "Done."
+            ]),
+            vec![
+                prose("Here:\n"),
+                Event::FenceOpen {
+                    info: "python-main.py".into()
+                },
+                code("print(1)"),
+                Event::FenceClose,
+                prose("Done."),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_and_tilde_fences() {
+        assert_eq!(
+            parse(&["````md\n```rust\nfn main() {}\n```\n````\n~~~\nls ```\n~~~"]),
+            vec![
+                Event::FenceOpen { info: "md".into() },
+                code("```rust"),
+                code("fn main() {}"),
+                code("```"),
+                Event::FenceClose,
+                Event::FenceOpen { info: "".into() },
+                code("ls ```"),
+                Event::FenceClose,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline_code() {
+        assert_eq!(
+            parse(&[
+                "Call `",
+                "run()` or ```x``` here, ",
+                "not ``this\n```a`b``` either"
+            ]),
+            vec![
+                prose("Call "),
+                Event::InlineCode("run()".into()),
+                prose(" or "),
+                Event::InlineCode("x".into()),
+                prose(" here, not ``this\n"),
+                Event::InlineCode("a`b".into()),
+                prose(" either"),
+            ]
+        );
+    }
+
+    // Pieces that make fences and inline code hard to tell apart
+    fn document() -> impl Strategy<Value = String> {
+        let piece = prop_oneof![
+            Just("`"),
+            Just("``"),
+            Just("```"),
+            Just("````"),
+            Just("~~~"),
+            Just("\n"),
+            Just(" "),
+            Just("rust-main.rs"),
+            Just("fn main() {}"),
+            Just("Some prose. "),
+        ];
+        proptest::collection::vec(piece, 0..40).prop_map(|pieces| pieces.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn test_chunking_does_not_change_events(
+            document in document(),
+            cuts in proptest::collection::vec(any::<prop::sample::Index>(), 0..20),
+        ) {
+            let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut.index(document.len() + 1)).collect();
+            cuts.sort_unstable();
+            let mut chunks = vec![];
+            let mut start = 0;
+            for cut in cuts {
+                chunks.push(&document[start..cut]);
+                start = cut;
+            }
+            chunks.push(&document[start..]);
+
+            prop_assert_eq!(parse(&chunks), parse(&[&document]));
+        }
+    }
+}
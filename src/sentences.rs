@@ -0,0 +1,195 @@
+// Lowercase words before a period that don't end the sentence
+const ABBREVIATIONS: &[&str] = &[
+    "approx", "cf", "dr", "eg", "ie", "jr", "mr", "mrs", "ms", "prof", "sr", "st", "vs",
+];
+// May follow the end of a sentence, as in `"Done."` or `(see above.)`
+const CLOSERS: &[char] = &['"', '\'', ')', ']', '*', '_', '`'];
+
+/// Splits streamed text into sentences as soon as they are complete.
+///
+/// A sentence ends at `.`, `!`, `?` or `:` followed by whitespace, or at a newline.
+/// Abbreviations like "e.g.", decimals like "3.14", and a period followed by
+/// a lowercase word don't end it. Sentences longer than `max_chars` are cut
+/// at a comma or a space so speech doesn't wait for them.
+pub struct SentenceSplitter {
+    buffer: String,
+    max_chars: usize,
+}
+
+impl SentenceSplitter {
+    pub fn new(max_chars: usize) -> Self {
+        Self {
+            buffer: String::new(),
+            max_chars,
+        }
+    }
+
+    /// Add streamed text and return the sentences it completes.
+    pub fn push(&mut self, text: &str) -> Vec<String> {
+        self.buffer.push_str(text);
+        let mut sentences = vec![];
+        while let Some(end) = sentence_end(&self.buffer).or_else(|| self.overflow()) {
+            let rest = self.buffer.split_off(end);
+            let sentence = std::mem::replace(&mut self.buffer, rest);
+            if !sentence.trim().is_empty() {
+                sentences.push(sentence);
+            }
+        }
+        sentences
+    }
+
+    /// The unfinished sentence at the end of the text, if any.
+    pub fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.buffer);
+        (!rest.trim().is_empty()).then_some(rest)
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Where to cut a sentence that has grown too long.
+    fn overflow(&self) -> Option<usize> {
+        let (limit, _) = self.buffer.char_indices().nth(self.max_chars)?;
+        let head = &self.buffer[..limit];
+        let cut = [", ", "; ", " "]
+            .iter()
+            .find_map(|separator| head.rfind(separator).map(|at| at + separator.len()))
+            .unwrap_or(limit);
+        Some(cut)
+    }
+}
+
+/// The byte offset just past the first complete sentence in `text`.
+fn sentence_end(text: &str) -> Option<usize> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let (at, c) = chars[i];
+        if c == '\n' {
+            return Some(at + 1);
+        }
+        if !matches!(c, '.' | '!' | '?' | ':') {
+            i += 1;
+            continue;
+        }
+
+        let mut end = i + 1;
+        while end < chars.len() && matches!(chars[end].1, '.' | '!' | '?') {
+            end += 1;
+        }
+        while end < chars.len() && CLOSERS.contains(&chars[end].1) {
+            end += 1;
+        }
+        // Wait for what comes next to tell
+        let (next_at, next) = *chars.get(end)?;
+        if !next.is_whitespace() {
+            i = end;
+            continue;
+        }
+        // The sentence keeps the space after it
+        let end_at = next_at + next.len_utf8();
+
+        if c == '.' {
+            let single_period = end == i + 1 || !matches!(chars[i + 1].1, '.');
+            if single_period && is_abbreviation(&text[..at]) {
+                i = end;
+                continue;
+            }
+            let gap = chars[end..].iter().take_while(|(_, c)| c.is_whitespace());
+            if gap.clone().any(|(_, c)| *c == '\n') {
+                return Some(end_at);
+            }
+            let (_, following) = *chars.get(end + gap.count())?;
+            if following.is_lowercase() {
+                i = end;
+                continue;
+            }
+        }
+        return Some(end_at);
+    }
+    None
+}
+
+/// Whether the word `text` ends with is shortened, so the period after it
+/// doesn't end the sentence. A number starting a line is a list item.
+fn is_abbreviation(text: &str) -> bool {
+    let start = text
+        .rfind(|c: char| c.is_whitespace() || c == '(')
+        .map_or(0, |at| at + 1);
+    let word = &text[start..];
+    if word.is_empty() {
+        return false;
+    }
+    if word.chars().all(|c| c.is_ascii_digit()) {
+        return text[..start].trim_end_matches(' ').ends_with('\n')
+            || text[..start].trim().is_empty();
+    }
+    let mut letters = word.chars();
+    if let (Some(letter), None) = (letters.next(), letters.next()) {
+        // An initial, but "I." ends a sentence
+        return letter.is_uppercase() && letter != 'I';
+    }
+    // "e.g", "i.e", "U.S"
+    if word.contains('.') && word.split('.').all(|part| part.chars().count() == 1) {
+        return true;
+    }
+    ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(tokens: &[&str]) -> Vec<String> {
+        let mut splitter = SentenceSplitter::new(200);
+        let mut sentences: Vec<String> = tokens
+            .iter()
+            .flat_map(|token| splitter.push(token))
+            .collect();
+        sentences.extend(splitter.finish());
+        sentences
+    }
+
+    #[test]
+    fn test_sentence_ends() {
+        assert_eq!(
+            split(&["Is it", " done?", " Yes", "! Run", " it:\n", "- first", "\n", "then"]),
+            vec!["Is it done? ", "Yes! ", "Run it:\n", "- first\n", "then"]
+        );
+        assert_eq!(
+            split(&["It's", " done.", " Next", " step."]),
+            vec!["It's done. ", "Next step."]
+        );
+        assert_eq!(
+            split(&["Two steps", ": build", ", then run at 10:30."]),
+            vec!["Two steps: ", "build, then run at 10:30."]
+        );
+    }
+
+    #[test]
+    fn test_periods_inside_sentences() {
+        assert_eq!(
+            split(&[
+                "Use e.g. pi = 3.14 in v1.2 here. ",
+                "Mr. Smith said wait... and then etc. stopped. ",
+                "1. Open main.rs first.",
+            ]),
+            vec![
+                "Use e.g. pi = 3.14 in v1.2 here. ",
+                "Mr. Smith said wait... and then etc. stopped. ",
+                "1. Open main.rs first.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_sentence_is_cut() {
+        let mut splitter = SentenceSplitter::new(20);
+        assert_eq!(
+            splitter.push("First part, second part and the rest"),
+            vec!["First part, ", "second part and the "]
+        );
+        assert_eq!(splitter.finish().as_deref(), Some("rest"));
+    }
+}
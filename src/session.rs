@@ -0,0 +1,34 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_openai::types::ChatCompletionRequestMessage;
+
+/// Seconds since the Unix epoch, to name files after when they were started.
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Pick a new session file under `home_dir`, named after the current time.
+pub fn new_session_path(home_dir: &Path) -> PathBuf {
+    home_dir
+        .join(".jarvy")
+        .join("sessions")
+        .join(format!("{}.json", timestamp()))
+}
+
+pub fn save(path: &Path, chat_history: &[ChatCompletionRequestMessage]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_string_pretty(chat_history)?;
+    std::fs::write(path, json)
+}
+
+pub fn load(path: &Path) -> io::Result<Vec<ChatCompletionRequestMessage>> {
+    let json = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&json)?)
+}
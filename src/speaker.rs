@@ -0,0 +1,509 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc as std_mpsc, Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use futures::StreamExt;
+use rodio::buffer::SamplesBuffer;
+use rodio::{Decoder, OutputStream, Sink, Source};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::config::TtsConfig;
+use crate::error::{Error, Result};
+use crate::sentences::SentenceSplitter;
+use crate::session;
+use crate::speech_engine::{self, SpeechAudio};
+use crate::speech_recorder::SpeechRecorder;
+use crate::speech_text;
+use crate::traits::SpeechEngine;
+
+// How often `finish` checks whether playback is done
+const PLAYBACK_POLL: Duration = Duration::from_millis(50);
+// Samples per playback level update, about 20 ms at 44.1 kHz stereo
+const METER_BLOCK: usize = 2048;
+// Sentences synthesized ahead of the one being played
+const PREFETCH: usize = 2;
+// How often the worker checks whether there is room in the queue
+const QUEUE_POLL: Duration = Duration::from_millis(50);
+// Longer sentences are cut, so the first words are heard sooner
+const MAX_SENTENCE_CHARS: usize = 300;
+
+/// Reads replies out loud with the speech engine picked at startup, or
+/// writes them to WAV files with `tts.output_dir`.
+///
+/// `push` hands each sentence to a background worker as soon as it is
+/// complete, and `flush` the rest; both return at once. The worker
+/// synthesizes up to `PREFETCH` sentences while the current one plays, and
+/// queues them on a single sink so they play back-to-back.
+pub struct Speaker {
+    sentences: SentenceSplitter,
+    engine: Arc<dyn SpeechEngine>,
+    recording_dir: Option<PathBuf>,
+    // Numbers the files of each reply when recording
+    turn: usize,
+    worker: Option<Worker>,
+}
+
+/// The worker task, started on first use.
+struct Worker {
+    playback: Arc<Playback>,
+    sentences: UnboundedSender<Sentence>,
+}
+
+/// What the speaker and its worker share.
+struct Playback {
+    target: Target,
+    // RMS of the audio being played, as `f32` bits
+    level: Arc<AtomicU32>,
+    // Bumped by `stop`, so sentences queued before are dropped
+    generation: AtomicU64,
+    // Sentences flushed but not on the sink yet
+    pending: AtomicUsize,
+}
+
+enum Target {
+    /// The default output device, opened when there is first something to play
+    Speakers(OnceLock<Output>),
+    Files(Mutex<SpeechRecorder>),
+}
+
+/// The default output device.
+///
+/// Its stream can't leave the thread that opened it, so it lives on a thread
+/// of its own until the sink is dropped.
+struct Output {
+    sink: Sink,
+    _close: std_mpsc::Sender<()>,
+}
+
+struct Sentence {
+    text: String,
+    generation: u64,
+    turn: usize,
+}
+
+impl Output {
+    fn open() -> Result<Self> {
+        let (opened, handle) = std_mpsc::channel();
+        let (close, closed) = std_mpsc::channel::<()>();
+        std::thread::spawn(move || match OutputStream::try_default() {
+            Ok((_stream, stream_handle)) => {
+                let _ = opened.send(Ok(stream_handle));
+                // Returns once `close` is dropped
+                let _ = closed.recv();
+            }
+            Err(err) => {
+                let _ = opened.send(Err(Error::AudioOutput(err.to_string())));
+            }
+        });
+        let stream_handle = handle
+            .recv()
+            .map_err(|_| Error::AudioOutput("the output thread has stopped".into()))??;
+        let sink =
+            Sink::try_new(&stream_handle).map_err(|err| Error::AudioOutput(err.to_string()))?;
+        Ok(Self {
+            sink,
+            _close: close,
+        })
+    }
+}
+
+impl Worker {
+    fn start(engine: Arc<dyn SpeechEngine>, recording_dir: Option<&Path>) -> Self {
+        let target = match recording_dir {
+            Some(dir) => Target::Files(Mutex::new(SpeechRecorder::new(dir))),
+            None => Target::Speakers(OnceLock::new()),
+        };
+        let playback = Arc::new(Playback {
+            target,
+            level: Arc::new(AtomicU32::new(0)),
+            generation: AtomicU64::new(0),
+            pending: AtomicUsize::new(0),
+        });
+
+        let (sentences, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run_worker(engine, receiver, playback.clone()));
+
+        Self {
+            playback,
+            sentences,
+        }
+    }
+}
+
+impl Playback {
+    fn is_current(&self, sentence: &Sentence) -> bool {
+        sentence.generation == self.generation.load(Ordering::SeqCst)
+    }
+
+    fn sink(&self) -> Option<&Sink> {
+        match &self.target {
+            Target::Speakers(output) => output.get().map(|output| &output.sink),
+            Target::Files(_) => None,
+        }
+    }
+
+    /// Sentences queued on the sink, including the one playing.
+    fn queued(&self) -> usize {
+        self.sink().map_or(0, Sink::len)
+    }
+
+    /// Queue audio after what is already playing, or write it to the files of `turn`.
+    fn play(&self, audio: SpeechAudio, turn: usize) -> Result<()> {
+        if audio.is_empty() {
+            return Ok(());
+        }
+        let output = match &self.target {
+            Target::Speakers(output) => output,
+            Target::Files(recorder) => {
+                return recorder.lock().unwrap().record(turn, &audio.into_pcm()?);
+            }
+        };
+        if output.get().is_none() {
+            // Only the worker sets it, so it can't be set in between
+            let _ = output.set(Output::open()?);
+        }
+        let source: Box<dyn Source<Item = f32> + Send> = match audio {
+            SpeechAudio::Encoded(data) => Box::new(
+                Decoder::new(Cursor::new(data))
+                    .map_err(|err| Error::AudioOutput(err.to_string()))?
+                    .convert_samples(),
+            ),
+            SpeechAudio::Pcm(pcm) => Box::new(
+                SamplesBuffer::new(pcm.channels, pcm.sample_rate, pcm.samples).convert_samples(),
+            ),
+        };
+        self.sink().unwrap().append(LevelMeter {
+            inner: source,
+            level: self.level.clone(),
+            sum: 0.0,
+            count: 0,
+        });
+        Ok(())
+    }
+}
+
+/// Synthesize sentences in order, a few at a time, and queue them for playback.
+///
+/// Runs until the speaker is dropped.
+async fn run_worker(
+    engine: Arc<dyn SpeechEngine>,
+    mut sentences: UnboundedReceiver<Sentence>,
+    playback: Arc<Playback>,
+) {
+    let sentences = futures::stream::poll_fn(move |cx| sentences.poll_recv(cx));
+    let mut synthesized = sentences
+        .map(|sentence| {
+            // Don't pay for sentences that were stopped before their turn
+            let audio = playback
+                .is_current(&sentence)
+                .then(|| engine.synthesize(sentence.text.clone()));
+            async move {
+                let audio = match audio {
+                    Some(audio) => Some(audio.await),
+                    None => None,
+                };
+                (sentence, audio)
+            }
+        })
+        .buffered(PREFETCH);
+
+    while let Some((sentence, audio)) = synthesized.next().await {
+        // Keep at most `PREFETCH` sentences waiting behind the one playing
+        while playback.is_current(&sentence) && playback.queued() > PREFETCH {
+            tokio::time::sleep(QUEUE_POLL).await;
+        }
+        if playback.is_current(&sentence) {
+            let audio = audio.unwrap_or(Ok(SpeechAudio::Encoded(vec![])));
+            // Opening the device, decoding and writing files all block
+            let target = playback.clone();
+            let played = tokio::task::spawn_blocking(move || {
+                audio.and_then(|audio| target.play(audio, sentence.turn))
+            })
+            .await
+            .unwrap_or_else(|err| Err(Error::AudioOutput(err.to_string())));
+            if let Err(err) = played {
+                eprintln!("\nError: {}", err);
+            }
+        }
+        playback.pending.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Passes audio through, publishing how loud it is for echo suppression.
+struct LevelMeter<S> {
+    inner: S,
+    level: Arc<AtomicU32>,
+    sum: f32,
+    count: usize,
+}
+
+impl<S: Source<Item = f32>> Iterator for LevelMeter<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+        self.sum += sample * sample;
+        self.count += 1;
+        if self.count == METER_BLOCK {
+            let rms = (self.sum / self.count as f32).sqrt();
+            self.level.store(rms.to_bits(), Ordering::Relaxed);
+            self.sum = 0.0;
+            self.count = 0;
+        }
+        Some(sample)
+    }
+}
+
+impl<S: Source<Item = f32>> Source for LevelMeter<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+impl Speaker {
+    pub fn new(config: &TtsConfig) -> Result<Self> {
+        // One directory per run, so earlier recordings are kept
+        let recording_dir = config
+            .output_dir
+            .as_ref()
+            .map(|dir| dir.join(session::timestamp().to_string()));
+        Ok(Self::with_engine(
+            speech_engine::from_config(config)?,
+            recording_dir,
+        ))
+    }
+
+    pub fn with_engine(engine: Box<dyn SpeechEngine>, recording_dir: Option<PathBuf>) -> Self {
+        Self {
+            sentences: SentenceSplitter::new(MAX_SENTENCE_CHARS),
+            engine: engine.into(),
+            recording_dir,
+            turn: 1,
+            worker: None,
+        }
+    }
+
+    /// Where speech is written instead of played, if it is.
+    pub fn recording_dir(&self) -> Option<&Path> {
+        self.recording_dir.as_deref()
+    }
+
+    /// Put what is flushed next in the files of a new turn.
+    pub fn end_turn(&mut self) {
+        self.turn += 1;
+    }
+
+    /// Add text, and queue the sentences it completes to be read out.
+    pub fn push(&mut self, chars: &[char]) -> Result<()> {
+        let text: String = chars.iter().collect();
+        for sentence in self.sentences.push(&text) {
+            self.queue(&sentence)?;
+        }
+        Ok(())
+    }
+
+    /// Queue the rest of the text, even if its last sentence is unfinished.
+    pub fn flush(&mut self) -> Result<()> {
+        match self.sentences.finish() {
+            Some(rest) => self.queue(&rest),
+            None => Ok(()),
+        }
+    }
+
+    /// Have `text` read out after what is already queued.
+    fn queue(&mut self, text: &str) -> Result<()> {
+        let text = speech_text::normalize(text);
+        if text.trim().is_empty() {
+            return Ok(());
+        }
+
+        let (engine, recording_dir) = (&self.engine, self.recording_dir.as_deref());
+        let worker = self
+            .worker
+            .get_or_insert_with(|| Worker::start(engine.clone(), recording_dir));
+        let playback = &worker.playback;
+        playback.pending.fetch_add(1, Ordering::SeqCst);
+        let sentence = Sentence {
+            text,
+            generation: playback.generation.load(Ordering::SeqCst),
+            turn: self.turn,
+        };
+        worker.sentences.send(sentence).map_err(|_| {
+            playback.pending.fetch_sub(1, Ordering::SeqCst);
+            Error::Tts("the speech worker has stopped".into())
+        })
+    }
+
+    /// Whether anything flushed is still being synthesized or played.
+    pub fn is_playing(&self) -> bool {
+        self.worker.as_ref().is_some_and(|worker| {
+            worker.playback.pending.load(Ordering::SeqCst) > 0 || worker.playback.queued() > 0
+        })
+    }
+
+    /// How loud the speech being played is, to tell its echo from the user.
+    /// 0 if nothing is playing.
+    pub fn playback_level(&self) -> f32 {
+        match &self.worker {
+            Some(worker) if worker.playback.queued() > 0 => {
+                f32::from_bits(worker.playback.level.load(Ordering::Relaxed))
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Stop playing and drop the sentences not read out yet.
+    pub fn stop(&mut self) {
+        self.sentences.clear();
+        if let Some(worker) = &self.worker {
+            worker.playback.generation.fetch_add(1, Ordering::SeqCst);
+            if let Some(sink) = worker.playback.sink() {
+                sink.stop();
+            }
+        }
+    }
+
+    /// Wait until everything flushed has been read out.
+    pub async fn finish(&mut self) {
+        while self.is_playing() {
+            tokio::time::sleep(PLAYBACK_POLL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::speech_engine::SilentEngine;
+
+    fn wav(samples: &[i16]) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 16_000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut data = Cursor::new(vec![]);
+        let mut writer = hound::WavWriter::new(&mut data, spec).unwrap();
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        data.into_inner()
+    }
+
+    /// Answers every request with `audio`, like the ElevenLabs API, and
+    /// keeps the requests it got.
+    fn elevenlabs_stand_in(audio: Vec<u8>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_url = format!(
+            "http://{}/v1/text-to-speech/",
+            listener.local_addr().unwrap()
+        );
+        let requests = Arc::new(Mutex::new(vec![]));
+        let received = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&mut stream);
+                let mut request = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                    request.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8_lossy(&body));
+                received.lock().unwrap().push(request);
+
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: audio/wav\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    audio.len()
+                )
+                .unwrap();
+                stream.write_all(&audio).unwrap();
+            }
+        });
+        (api_url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_speech_is_written_to_files() {
+        let (api_url, requests) = elevenlabs_stand_in(wav(&[100, 200, 300]));
+        let config = TtsConfig {
+            api_url,
+            api_key: Some("test-key".into()),
+            ..TtsConfig::default()
+        };
+        let dir = std::env::temp_dir().join(format!("jarvy-speech-{}", std::process::id()));
+        let mut speaker = Speaker::with_engine(
+            speech_engine::from_config(&config).unwrap(),
+            Some(dir.clone()),
+        );
+
+        for sentence in ["Hello there. ", "How are you? "] {
+            speaker.push(&sentence.chars().collect::<Vec<_>>()).unwrap();
+        }
+        speaker.end_turn();
+        speaker.push(&"Bye. ".chars().collect::<Vec<_>>()).unwrap();
+        speaker.flush().unwrap();
+        speaker.finish().await;
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].starts_with(&format!("POST /v1/text-to-speech/{}", config.voice_id)));
+        assert!(requests[0].contains("xi-api-key: test-key"));
+        assert!(requests[1].contains("How are you?"));
+
+        let samples = |name: &str| {
+            hound::WavReader::open(dir.join(name))
+                .unwrap()
+                .into_samples::<i16>()
+                .count()
+        };
+        assert_eq!(samples("turn-001-01.wav"), 3);
+        assert_eq!(samples("turn-001.wav"), 6);
+        assert_eq!(samples("turn-002.wav"), 3);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_silent_engine_needs_no_output_device() {
+        let mut speaker = Speaker::with_engine(Box::new(SilentEngine), None);
+        speaker
+            .push(&"Hello there. ".chars().collect::<Vec<_>>())
+            .unwrap();
+        speaker.flush().unwrap();
+        speaker.finish().await;
+
+        let worker = speaker.worker.as_ref().unwrap();
+        assert!(worker.playback.sink().is_none());
+        assert_eq!(speaker.playback_level(), 0.0);
+    }
+}
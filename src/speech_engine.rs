@@ -0,0 +1,398 @@
+use std::future::Future;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use reqwest::Client;
+use rodio::{Decoder, Source};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::config::{TtsConfig, TtsEngineKind};
+use crate::error::{Error, Result};
+use crate::traits::SpeechEngine;
+
+pub type SpeechFuture = Pin<Box<dyn Future<Output = Result<SpeechAudio>> + Send>>;
+
+/// A synthesized sentence.
+pub enum SpeechAudio {
+    /// A file rodio can decode, such as WAV or MP3
+    Encoded(Vec<u8>),
+    Pcm(Pcm),
+}
+
+/// Interleaved 16-bit samples.
+#[derive(Debug, Default, PartialEq)]
+pub struct Pcm {
+    pub samples: Vec<i16>,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl SpeechAudio {
+    /// No audio, so nothing to play.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            SpeechAudio::Encoded(data) => data.is_empty(),
+            SpeechAudio::Pcm(pcm) => pcm.samples.is_empty(),
+        }
+    }
+
+    /// Decode the audio if it isn't PCM already.
+    pub fn into_pcm(self) -> Result<Pcm> {
+        match self {
+            SpeechAudio::Encoded(data) => {
+                let decoder = Decoder::new(Cursor::new(data))
+                    .map_err(|err| Error::AudioOutput(err.to_string()))?;
+                Ok(Pcm {
+                    sample_rate: decoder.sample_rate(),
+                    channels: decoder.channels(),
+                    samples: decoder.collect(),
+                })
+            }
+            SpeechAudio::Pcm(pcm) => Ok(pcm),
+        }
+    }
+}
+
+impl Pcm {
+    /// From headerless little-endian 16-bit samples.
+    pub fn from_s16le(data: &[u8], sample_rate: u32, channels: u16) -> Self {
+        Self {
+            samples: data
+                .chunks_exact(2)
+                .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+                .collect(),
+            sample_rate,
+            channels,
+        }
+    }
+}
+
+// Keeps the temporary files of concurrent `say` runs apart
+static SAY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// The engine picked in the config.
+pub fn from_config(config: &TtsConfig) -> Result<Box<dyn SpeechEngine>> {
+    Ok(match config.engine {
+        TtsEngineKind::ElevenLabs => Box::new(ElevenLabsEngine::new(config)),
+        TtsEngineKind::OpenAi => Box::new(OpenAiEngine::new(config)),
+        TtsEngineKind::Piper => Box::new(PiperEngine::new(config)?),
+        TtsEngineKind::Espeak => Box::new(EspeakEngine::new(config)),
+        TtsEngineKind::Say => Box::new(SayEngine::new(config)),
+        TtsEngineKind::Silent => Box::new(SilentEngine),
+    })
+}
+
+#[derive(Serialize)]
+struct VoiceSettings {
+    stability: u32,
+    similarity_boost: u32,
+}
+
+#[derive(Serialize)]
+struct ElevenLabsRequest {
+    text: String,
+    voice_settings: VoiceSettings,
+}
+
+/// ElevenLabs text-to-speech API, MP3 audio.
+pub struct ElevenLabsEngine {
+    client: Client,
+    url: String,
+    api_key: String,
+}
+
+impl ElevenLabsEngine {
+    pub fn new(config: &TtsConfig) -> Self {
+        Self {
+            client: Client::new(),
+            url: format!("{}{}", config.api_url, config.voice_id),
+            api_key: config.api_key.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl SpeechEngine for ElevenLabsEngine {
+    fn synthesize(&self, text: String) -> SpeechFuture {
+        let request = self
+            .client
+            .post(&self.url)
+            .header("xi-api-key", &self.api_key)
+            .json(&ElevenLabsRequest {
+                text,
+                voice_settings: VoiceSettings {
+                    stability: 0,
+                    similarity_boost: 0,
+                },
+            });
+        Box::pin(async move { fetch_audio(request).await })
+    }
+}
+
+#[derive(Serialize)]
+struct OpenAiRequest {
+    model: String,
+    input: String,
+    voice: String,
+    response_format: &'static str,
+}
+
+/// OpenAI text-to-speech API, MP3 audio.
+///
+/// The API key is read from the `OPENAI_API_KEY` environment variable.
+pub struct OpenAiEngine {
+    client: Client,
+    api_url: String,
+    api_key: String,
+    model: String,
+    voice: String,
+}
+
+impl OpenAiEngine {
+    pub fn new(config: &TtsConfig) -> Self {
+        Self {
+            client: Client::new(),
+            api_url: config.openai.api_url.clone(),
+            api_key: config.openai.api_key.clone().unwrap_or_default(),
+            model: config.openai.model.clone(),
+            voice: config.openai.voice.clone(),
+        }
+    }
+}
+
+impl SpeechEngine for OpenAiEngine {
+    fn synthesize(&self, text: String) -> SpeechFuture {
+        let request = self
+            .client
+            .post(&self.api_url)
+            .bearer_auth(&self.api_key)
+            .json(&OpenAiRequest {
+                model: self.model.clone(),
+                input: text,
+                voice: self.voice.clone(),
+                response_format: "mp3",
+            });
+        Box::pin(async move { fetch_audio(request).await })
+    }
+}
+
+async fn fetch_audio(request: reqwest::RequestBuilder) -> Result<SpeechAudio> {
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(Error::Tts(response.status().to_string()));
+    }
+    Ok(SpeechAudio::Encoded(response.bytes().await?.to_vec()))
+}
+
+/// Piper neural voices, run locally from an ONNX model. Raw PCM audio.
+pub struct PiperEngine {
+    executable: String,
+    model: PathBuf,
+    speaker: Option<u32>,
+    length_scale: f32,
+    sample_rate: u32,
+}
+
+impl PiperEngine {
+    pub fn new(config: &TtsConfig) -> Result<Self> {
+        let piper = &config.piper;
+        let model = piper
+            .model
+            .clone()
+            .ok_or_else(|| Error::Tts("tts.piper.model is not set".into()))?;
+        Ok(Self {
+            executable: piper.executable.clone(),
+            sample_rate: piper_sample_rate(&model)?,
+            model,
+            speaker: piper.speaker,
+            length_scale: piper.length_scale,
+        })
+    }
+}
+
+impl SpeechEngine for PiperEngine {
+    fn synthesize(&self, text: String) -> SpeechFuture {
+        let mut command = Command::new(&self.executable);
+        command
+            .arg("--model")
+            .arg(&self.model)
+            .arg("--length_scale")
+            .arg(self.length_scale.to_string())
+            .arg("--output_raw");
+        if let Some(speaker) = self.speaker {
+            command.arg("--speaker").arg(speaker.to_string());
+        }
+        // Piper reads one utterance per line
+        let text = text.replace('\n', " ");
+        let sample_rate = self.sample_rate;
+        Box::pin(async move {
+            let data = run(command, Some(text)).await?;
+            Ok(SpeechAudio::Pcm(Pcm::from_s16le(&data, sample_rate, 1)))
+        })
+    }
+}
+
+/// The sample rate in the config Piper keeps next to a voice, `<model>.json`.
+fn piper_sample_rate(model: &Path) -> Result<u32> {
+    let mut path = model.as_os_str().to_owned();
+    path.push(".json");
+    let path = PathBuf::from(path);
+    let data = std::fs::read(&path)
+        .map_err(|err| Error::Tts(format!("could not read {}: {}", path.display(), err)))?;
+    let voice: serde_json::Value = serde_json::from_slice(&data)
+        .map_err(|err| Error::Tts(format!("invalid {}: {}", path.display(), err)))?;
+    voice["audio"]["sample_rate"]
+        .as_u64()
+        .map(|rate| rate as u32)
+        .ok_or_else(|| Error::Tts(format!("no audio.sample_rate in {}", path.display())))
+}
+
+/// The `espeak-ng` command, WAV audio. Robotic, but offline and on every Linux distribution.
+pub struct EspeakEngine {
+    voice: String,
+    words_per_minute: u32,
+}
+
+impl EspeakEngine {
+    pub fn new(config: &TtsConfig) -> Self {
+        Self {
+            voice: config.espeak.voice.clone(),
+            words_per_minute: config.espeak.words_per_minute,
+        }
+    }
+}
+
+impl SpeechEngine for EspeakEngine {
+    fn synthesize(&self, text: String) -> SpeechFuture {
+        let mut command = Command::new("espeak-ng");
+        command
+            .arg("--stdout")
+            .arg("-v")
+            .arg(&self.voice)
+            .arg("-s")
+            .arg(self.words_per_minute.to_string())
+            // On stdin, so a sentence starting with `-` isn't read as an option
+            .arg("--stdin");
+        Box::pin(async move { Ok(SpeechAudio::Encoded(run(command, Some(text)).await?)) })
+    }
+}
+
+/// The macOS `say` command, rendered to a WAV file so it plays like the other engines.
+pub struct SayEngine {
+    voice: String,
+    rate: u32,
+}
+
+impl SayEngine {
+    pub fn new(config: &TtsConfig) -> Self {
+        Self {
+            voice: config.say.voice.clone(),
+            rate: config.say.rate,
+        }
+    }
+}
+
+impl SpeechEngine for SayEngine {
+    fn synthesize(&self, text: String) -> SpeechFuture {
+        // `say` can only write audio to a file
+        let path = std::env::temp_dir().join(format!(
+            "jarvy-say-{}-{}.wav",
+            std::process::id(),
+            SAY_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut command = Command::new("say");
+        command
+            .arg("-r")
+            .arg(self.rate.to_string())
+            .arg("-v")
+            .arg(&self.voice)
+            .arg("--file-format=WAVE")
+            .arg("--data-format=LEI16@22050")
+            .arg("-o")
+            .arg(&path)
+            // On stdin, so a sentence starting with `-` isn't read as an option
+            .arg("-f")
+            .arg("-");
+        Box::pin(async move {
+            run(command, Some(text)).await?;
+            let audio = tokio::fs::read(&path).await;
+            let _ = tokio::fs::remove_file(&path).await;
+            Ok(SpeechAudio::Encoded(audio?))
+        })
+    }
+}
+
+/// No speech at all, for when replies should only be read on screen.
+pub struct SilentEngine;
+
+impl SpeechEngine for SilentEngine {
+    fn synthesize(&self, _text: String) -> SpeechFuture {
+        Box::pin(async { Ok(SpeechAudio::Pcm(Pcm::default())) })
+    }
+}
+
+/// Run a speech command with `input` on stdin and return what it wrote to stdout.
+async fn run(mut command: Command, input: Option<String>) -> Result<Vec<u8>> {
+    let program = command.as_std().get_program().to_string_lossy().to_string();
+    let mut child = command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| Error::Tts(format!("could not run {}: {}", program, err)))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes()).await?;
+        // Dropping stdin closes it, so the command sees the end of the text
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(Error::Tts(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_missing_command_is_a_tts_error() {
+        let result = run(Command::new("jarvy-no-such-speech-command"), None).await;
+        assert!(matches!(result, Err(Error::Tts(message)) if message.contains("could not run")));
+    }
+
+    #[test]
+    fn test_piper_sample_rate() {
+        let dir = std::env::temp_dir().join(format!("jarvy-piper-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let model = dir.join("en_US-lessac-medium.onnx");
+        std::fs::write(
+            dir.join("en_US-lessac-medium.onnx.json"),
+            r#"{"audio": {"sample_rate": 22050}, "num_speakers": 1}"#,
+        )
+        .unwrap();
+
+        assert_eq!(piper_sample_rate(&model).unwrap(), 22050);
+        assert!(piper_sample_rate(&dir.join("missing.onnx")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_pcm_from_s16le() {
+        let pcm = Pcm::from_s16le(&[0x01, 0x00, 0xff, 0xff, 0x7f], 16000, 1);
+        assert_eq!(pcm.samples, vec![1, -1]);
+        assert!(!SpeechAudio::Pcm(pcm).is_empty());
+    }
+}
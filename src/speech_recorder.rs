@@ -0,0 +1,113 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use crate::error::{Error, Result};
+use crate::speech_engine::Pcm;
+
+/// Writes speech to WAV files instead of playing it.
+///
+/// Each sentence goes to `turn-<turn>-<sentence>.wav`, and the whole turn to
+/// `turn-<turn>.wav`, which is valid after every sentence.
+pub struct SpeechRecorder {
+    dir: PathBuf,
+    turn: usize,
+    sentence: usize,
+    turn_writer: Option<WavWriter<BufWriter<File>>>,
+}
+
+impl SpeechRecorder {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            turn: 0,
+            sentence: 0,
+            turn_writer: None,
+        }
+    }
+
+    /// Write the next sentence of `turn`. A new turn number starts a new turn file.
+    pub fn record(&mut self, turn: usize, pcm: &Pcm) -> Result<()> {
+        if turn != self.turn {
+            if let Some(writer) = self.turn_writer.take() {
+                writer.finalize()?;
+            }
+            self.turn = turn;
+            self.sentence = 0;
+        }
+        self.sentence += 1;
+        std::fs::create_dir_all(&self.dir)?;
+
+        let spec = WavSpec {
+            channels: pcm.channels,
+            sample_rate: pcm.sample_rate,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let path = self
+            .dir
+            .join(format!("turn-{:03}-{:02}.wav", self.turn, self.sentence));
+        let mut writer = WavWriter::create(path, spec)?;
+        write_samples(&mut writer, pcm)?;
+        writer.finalize()?;
+
+        if self.turn_writer.is_none() {
+            let path = self.dir.join(format!("turn-{:03}.wav", self.turn));
+            self.turn_writer = Some(WavWriter::create(path, spec)?);
+        }
+        let writer = self.turn_writer.as_mut().unwrap();
+        if writer.spec() != spec {
+            return Err(Error::AudioOutput(format!(
+                "turn {} changes audio format mid-turn",
+                self.turn
+            )));
+        }
+        write_samples(writer, pcm)?;
+        // Updates the header, so the turn file can be played before the turn ends
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn write_samples(writer: &mut WavWriter<BufWriter<File>>, pcm: &Pcm) -> Result<()> {
+    for &sample in &pcm.samples {
+        writer.write_sample(sample)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm(samples: Vec<i16>) -> Pcm {
+        Pcm {
+            samples,
+            sample_rate: 16_000,
+            channels: 1,
+        }
+    }
+
+    #[test]
+    fn test_turn_file_has_every_sentence() {
+        let dir = std::env::temp_dir().join(format!("jarvy-recorder-{}", std::process::id()));
+        let mut recorder = SpeechRecorder::new(&dir);
+        recorder.record(1, &pcm(vec![1, 2])).unwrap();
+        recorder.record(1, &pcm(vec![3])).unwrap();
+        recorder.record(2, &pcm(vec![4])).unwrap();
+
+        let samples = |name: &str| {
+            hound::WavReader::open(dir.join(name))
+                .unwrap()
+                .into_samples::<i16>()
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        assert_eq!(samples("turn-001-02.wav"), vec![3]);
+        assert_eq!(samples("turn-001.wav"), vec![1, 2, 3]);
+        assert_eq!(samples("turn-002.wav"), vec![4]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
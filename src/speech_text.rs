@@ -0,0 +1,332 @@
+// Spoken in place of operators, longest first so `<=` isn't read as `<`
+const OPERATORS: &[(&str, &str)] = &[
+    ("->", "to"),
+    ("=>", "to"),
+    ("==", "equals"),
+    ("!=", "is not"),
+    ("<=", "at most"),
+    (">=", "at least"),
+    ("&&", "and"),
+    ("||", "or"),
+    ("+=", "plus equals"),
+    ("-=", "minus equals"),
+    ("=", "equals"),
+    ("+", "plus"),
+    ("&", "and"),
+    ("<", "less than"),
+    (">", "greater than"),
+];
+
+/// Turn the markdown of a reply into text that sounds natural read out.
+///
+/// Formatting, tables, URLs and images are dropped, links keep their text,
+/// and code like `parse_args()` or `src/main.rs` is read as words.
+pub fn normalize(text: &str) -> String {
+    text.split('\n')
+        .filter_map(normalize_line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn normalize_line(line: &str) -> Option<String> {
+    let line = line.trim();
+    if is_table_row(line) || is_rule(line) {
+        return None;
+    }
+    let mut spoken = String::new();
+    for segment in split_inline(strip_block_prefix(line)) {
+        let words = match segment {
+            Segment::Prose(prose) => speak_prose(&prose),
+            Segment::Code(code) => speak_code(&code),
+        };
+        if !words.is_empty() {
+            if !spoken.is_empty() && !words.starts_with(|c: char| ",.;:!?)".contains(c)) {
+                spoken.push(' ');
+            }
+            spoken.push_str(&words);
+        }
+    }
+    Some(spoken)
+}
+
+fn is_table_row(line: &str) -> bool {
+    line.starts_with('|')
+}
+
+fn is_rule(line: &str) -> bool {
+    let marks: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3
+        && ["-", "*", "_"]
+            .iter()
+            .any(|mark| marks.replace(mark, "").is_empty())
+}
+
+/// Drop heading, quote and list markers.
+fn strip_block_prefix(mut line: &str) -> &str {
+    loop {
+        let stripped = if let Some(rest) = line.strip_prefix('>') {
+            rest
+        } else if line.starts_with('#') {
+            match line.trim_start_matches('#').strip_prefix(' ') {
+                Some(rest) => rest,
+                None => return line,
+            }
+        } else if let Some(rest) = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|bullet| line.strip_prefix(bullet))
+        {
+            rest
+        } else {
+            let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            match line[digits..]
+                .strip_prefix(". ")
+                .or_else(|| line[digits..].strip_prefix(") "))
+            {
+                Some(rest) if digits > 0 => rest,
+                _ => return line,
+            }
+        };
+        line = stripped.trim_start();
+    }
+}
+
+enum Segment {
+    Prose(String),
+    Code(String),
+}
+
+/// Split a line into prose and inline code, keeping only the text of links
+/// and dropping images.
+fn split_inline(line: &str) -> Vec<Segment> {
+    let mut segments = vec![];
+    let mut prose = String::new();
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        if c == '`' {
+            let ticks = rest.len() - rest.trim_start_matches('`').len();
+            let fence = &rest[..ticks];
+            if let Some(end) = rest[ticks..].find(fence) {
+                segments.push(Segment::Prose(std::mem::take(&mut prose)));
+                segments.push(Segment::Code(rest[ticks..ticks + end].to_string()));
+                rest = &rest[ticks + end + ticks..];
+            } else {
+                // Unclosed, e.g. cut off at a flush
+                rest = &rest[ticks..];
+            }
+            continue;
+        }
+        if let Some((text, after)) = link(rest) {
+            if c != '!' {
+                prose.push_str(text);
+            }
+            rest = after;
+            continue;
+        }
+        prose.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    segments.push(Segment::Prose(prose));
+    segments
+}
+
+/// `[text](url)`, `[text][ref]` or `![alt](url)` at the start of `text`,
+/// with the rest of it.
+fn link(text: &str) -> Option<(&str, &str)> {
+    let label = text.strip_prefix('!').unwrap_or(text).strip_prefix('[')?;
+    let end = label.find(']')?;
+    let after = &label[end + 1..];
+    let close = match after.chars().next()? {
+        '(' => ')',
+        '[' => ']',
+        _ => return None,
+    };
+    let target_end = after.find(close)?;
+    Some((&label[..end], &after[target_end + 1..]))
+}
+
+fn speak_prose(prose: &str) -> String {
+    let prose = prose.replace("**", "").replace("~~", "").replace('*', "");
+    prose
+        .split_whitespace()
+        .filter_map(speak_word)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn speak_word(word: &str) -> Option<String> {
+    if is_url(word) {
+        return None;
+    }
+    if let Some((_, spoken)) = OPERATORS.iter().find(|(operator, _)| *operator == word) {
+        return Some(spoken.to_string());
+    }
+    // Emphasis, unless the underscores are part of an identifier
+    let word = word.trim_matches('_');
+    let core = word.trim_end_matches(|c: char| ",.;:!?)\"'".contains(c));
+    let trailing = &word[core.len()..];
+    let leading_len = core.len() - core.trim_start_matches(['(', '"', '\'']).len();
+    let (leading, core) = core.split_at(leading_len);
+    if core.is_empty() {
+        return (!word.is_empty()).then(|| word.to_string());
+    }
+
+    let spoken = if is_code_like(core) {
+        speak_code(core)
+    } else if is_version(core) {
+        format!(
+            "version {}",
+            core.trim_start_matches('v').replace('.', " point ")
+        )
+    } else {
+        core.to_string()
+    };
+    Some(format!("{}{}{}", leading, spoken, trailing))
+}
+
+fn is_url(word: &str) -> bool {
+    let word = word.trim_start_matches(['(', '<']);
+    ["http://", "https://", "www."]
+        .iter()
+        .any(|prefix| word.starts_with(prefix))
+}
+
+/// Identifiers, calls and paths that would be read out badly as they are.
+fn is_code_like(word: &str) -> bool {
+    word.contains('_')
+        || word.contains("::")
+        || word.ends_with("()")
+        || is_path(word)
+        || is_file_name(word)
+}
+
+fn is_path(word: &str) -> bool {
+    word.contains('/') && (word.contains('.') || word.starts_with('/') || word.starts_with("~/"))
+}
+
+/// Like `main.rs` or `Node.js`, but not `e.g` or `3.14`.
+fn is_file_name(word: &str) -> bool {
+    let Some((stem, extension)) = word.rsplit_once('.') else {
+        return false;
+    };
+    stem.chars().filter(|c| c.is_alphanumeric()).count() >= 2
+        && (1..=4).contains(&extension.len())
+        && extension.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+/// Like `1.2.3`.
+fn is_version(word: &str) -> bool {
+    let word = word.strip_prefix('v').unwrap_or(word);
+    word.split('.').count() >= 3
+        && word
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Read code as words: `std::fs::read_to_string` is "std fs read to string".
+fn speak_code(code: &str) -> String {
+    if is_url(code.trim()) {
+        return String::new();
+    }
+    let code = code.trim();
+    let mut code = code
+        .strip_prefix("./")
+        .unwrap_or(code)
+        .replace("()", " ")
+        .replace("::", " ");
+    // Single characters only when they stand alone, `&str` and `Vec<u8>` aren't maths
+    for (operator, spoken) in OPERATORS.iter().filter(|(operator, _)| operator.len() > 1) {
+        code = code.replace(operator, &format!(" {} ", spoken));
+    }
+    code.split_whitespace()
+        .map(
+            |token| match OPERATORS.iter().find(|(operator, _)| *operator == token) {
+                Some((_, spoken)) => spoken.to_string(),
+                None => speak_identifiers(token),
+            },
+        )
+        .filter(|words| !words.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn speak_identifiers(token: &str) -> String {
+    token
+        .replace('.', " dot ")
+        .replace('/', " slash ")
+        .replace(|c: char| !c.is_alphanumeric() && c != '_', " ")
+        .split_whitespace()
+        .map(split_identifier)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `parse_args` is "parse args", `HashMap` is "Hash Map", `HTTPServer` is "HTTP Server".
+fn split_identifier(word: &str) -> String {
+    let chars: Vec<char> = word.chars().collect();
+    let mut words = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' {
+            if !words.is_empty() && !words.ends_with(' ') {
+                words.push(' ');
+            }
+            continue;
+        }
+        if i > 0 && c.is_uppercase() && !words.ends_with(' ') {
+            let previous = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_is_lower)
+            {
+                words.push(' ');
+            }
+        }
+        words.push(c);
+    }
+    words.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_is_stripped() {
+        assert_eq!(
+            normalize("## Setup\n- Run **cargo build** first.\n> *Note:* it takes a while."),
+            "Setup\nRun cargo build first.\nNote: it takes a while."
+        );
+        assert_eq!(
+            normalize("See [the docs](https://docs.rs/rodio) or https://example.com for more."),
+            "See the docs or for more."
+        );
+        assert_eq!(
+            normalize("| a | b |\n|---|---|\n| 1 | 2 |\n---\n![diagram](flow.png)Done."),
+            "Done."
+        );
+    }
+
+    #[test]
+    fn test_code_is_read_as_words() {
+        assert_eq!(
+            normalize("Call `parse_args()` in src/main.rs, it returns `Result<Config>`."),
+            "Call parse args in src slash main dot rs, it returns Result Config."
+        );
+        assert_eq!(
+            normalize("Use `std::fs::read_to_string` or HashMap::new, e.g. in Node.js."),
+            "Use std fs read to string or Hash Map new, e.g. in Node dot js."
+        );
+    }
+
+    #[test]
+    fn test_operators_and_numbers() {
+        assert_eq!(
+            normalize("If a != b -> return 3.14 from v1.2.3"),
+            "If a is not b to return 3.14 from version 1 point 2 point 3"
+        );
+        assert_eq!(
+            normalize("x = 1 && `y >= 2`"),
+            "x equals 1 and y at least 2"
+        );
+    }
+}
@@ -1,197 +1,748 @@
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Stream, StreamConfig};
-use rubato::{InterpolationParameters, InterpolationType, Resampler, SincFixedIn, WindowFunction};
-use std::sync::mpsc::{self, Receiver, SyncSender};
+use cpal::traits::StreamTrait;
+use std::io::{stdout, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
 use std::time::{Duration, Instant};
-use whisper_rs::{convert_stereo_to_mono_audio, FullParams, SamplingStrategy, WhisperContext};
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperToken};
 
-use crate::traits::GetInput;
+use crate::audio_input;
+use crate::audio_source::{self, OUTPUT_SAMPLE_RATE};
+use crate::barge_in::BargeInDetector;
+use crate::config::{SttConfig, Trigger, WhisperConfig};
+use crate::error::{Error, Result};
+use crate::keys::{Hotkey, Hotkeys};
+use crate::keyword::Keyword;
+use crate::traits::{AudioSource, GetInput, Interruption};
+use crate::transcript::{Segment, Token, Transcript};
+use crate::vad::VoiceActivityDetector;
+use crate::vocabulary;
 
-const VOLUME_THRESHOLD: f32 = 0.05;
-const SILENCE_DURATION: Duration = Duration::from_secs(1);
-const AUDIO_BUFFER: usize = 512;
-const OUTPUT_SAMPLE_RATE: usize = 16_000;  // as required by Whisper
+// Room for the chunks that arrive while Whisper is running,
+// about 2 min of resampled 512-frame chunks
+const CHANNEL_CAPACITY: usize = 4096;
 
-pub struct Stt {
+// How often the streaming hypothesis is updated
+const STREAMING_STEP: Duration = Duration::from_millis(1000);
+// Streaming commits text once the window grows past this many samples
+const STREAMING_WINDOW: usize = OUTPUT_SAMPLE_RATE * 10;
+const PARTIAL_WIDTH: usize = 76;
+
+// How often the keys are checked while recording
+const KEY_POLL: Duration = Duration::from_millis(50);
+// A second press sooner than this is the key bouncing or auto-repeating
+const TOGGLE_DEBOUNCE: Duration = Duration::from_millis(300);
+
+// Only the start of an utterance is checked for the wake word
+const WAKE_WINDOW: usize = OUTPUT_SAMPLE_RATE * 2;
+// Speech heard during the reply is checked for the stop word once this long
+const STOP_WINDOW: usize = OUTPUT_SAMPLE_RATE * 3 / 2;
+// Encode about 10 s instead of Whisper's full 30 s window, which is
+// plenty for the keyword windows and several times faster
+const SPOT_AUDIO_CTX: i32 = 512;
+const SPOT_MAX_TOKENS: i32 = 8;
+
+/// Why a recording stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    Timeout,
+    Ended,
+    Cancelled,
+}
+
+/// The Whisper model and how to decode with it, independent of where the audio comes from.
+pub struct Transcriber {
     ctx: WhisperContext,
+    options: WhisperConfig,
+    // `None` to detect the language
+    language: Option<&'static str>,
+    prompt_tokens: Vec<WhisperToken>,
+}
+
+pub struct Stt {
+    transcriber: Transcriber,
     audio_data: Vec<f32>,
-    audio_receiver: Receiver<f32>,
+    audio_receiver: Receiver<Vec<f32>>,
     stream: cpal::platform::Stream,
+    trigger: Trigger,
+    streaming: bool,
+    vad: VoiceActivityDetector,
+    max_utterance_len: usize,
+    // Where the project vocabulary for the Whisper prompt comes from
+    home_dir: Option<PathBuf>,
+    recent: Vec<String>,
+    wake_word: Keyword,
+    wake_phrase: String,
+    // The wake word alone, to prime the spotting pass
+    wake_tokens: Vec<WhisperToken>,
+    stop_word: Keyword,
+    stop_tokens: Vec<WhisperToken>,
+    // Hears the user during the reply, if they can barge in or say the stop word
+    listener: Option<BargeInDetector>,
+    barge_in: bool,
+    listening: bool,
+    // Speech during the reply, collected to check for the stop word
+    heard: Vec<f32>,
+    // What the user said over the reply, to start the next utterance with
+    barged_in_audio: Vec<f32>,
+    // Whether the current utterance began with it
+    started_over_reply: bool,
 }
 
-fn audio_input_stream_data_callback(
-    n_channels: usize,
-    raw_stereo_samples: &[f32],
-    tx: &SyncSender<f32>,
-    resampler: &mut SincFixedIn<f32>,
-) {
-    // Convert stereo to mono
-    let mut mono_samples = if n_channels == 2 {
-        let raw_mono_samples = convert_stereo_to_mono_audio(raw_stereo_samples).unwrap();
-        // Resample the audio to get the target sample rate
-        resampler.process(&[raw_mono_samples], None).unwrap()
-    } else {
-        resampler.process(&[raw_stereo_samples], None).unwrap()
-    };
+impl GetInput for Stt {
+    /// Record an utterance, then output the text.
+    ///
+    /// Esc discards the utterance and starts over.
+    fn record(&mut self) -> Result<Transcript> {
+        self.listening = false;
+        self.update_prompt()?;
+        loop {
+            let transcript = match self.trigger {
+                Trigger::PushToTalk => self.record_push_to_talk()?,
+                Trigger::Voice if self.streaming => self.record_streaming()?,
+                Trigger::Voice => self.record_until_silence()?,
+                Trigger::WakeWord => self.record_after_wake_word()?,
+            };
+            match transcript {
+                Some(transcript) => return Ok(transcript),
+                None => println!("(discarded)"),
+            }
+        }
+    }
 
-    // Send the audio to the main thread
-    mono_samples.pop().unwrap().into_iter().for_each(|sample| {
-        tx.send(sample)
-            .expect("Failed to send audio sample to main thread");
-    });
-}
+    fn set_context(&mut self, recent: &[String]) {
+        self.recent = recent.to_vec();
+    }
 
-fn create_paused_audio_stream(tx: SyncSender<f32>) -> Stream {
-    // Get the default host and input device
-    let host = cpal::default_host();
-    let input_device = host
-        .default_input_device()
-        .expect("Failed to get default input device");
-    println!("Default input device: {:?}", input_device.name());
-
-    // Configure the input stream with default format
-    // We want to use the default format
-    let input_config: StreamConfig = input_device
-        .supported_input_configs()
-        .expect("No supported input config found")
-        .next()
-        .expect("No supported input config found")
-        .with_max_sample_rate()
-        .into();
-    println!("Input config: {:?}", input_config);
-
-    let n_channels = input_config.channels as usize;
-
-    // Create resampler to convert the audio from the input device's sample rate to 16 kHz
-    let mut mono_resampler = SincFixedIn::<f32>::new(
-        OUTPUT_SAMPLE_RATE as f64 / input_config.sample_rate.0 as f64,
-        2.0,
-        InterpolationParameters {
-            sinc_len: 128,
-            f_cutoff: 0.95,
-            interpolation: InterpolationType::Linear,
-            oversampling_factor: 128,
-            window: WindowFunction::BlackmanHarris2,
-        },
-        AUDIO_BUFFER,
-        1,
-    )
-    .unwrap();
-
-    // Build and play the input stream
-    let stream = input_device
-        .build_input_stream(
-            &input_config,
-            move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                audio_input_stream_data_callback(n_channels, data, &tx, &mut mono_resampler);
-            },
-            move |err| eprintln!("An error occurred on the input audio stream: {}", err),
-            None,
-        )
-        .expect("Failed to build input stream");
+    fn start_listening(&mut self) -> Result<()> {
+        let Some(detector) = &mut self.listener else {
+            return Ok(());
+        };
+        detector.start(self.vad.noise_floor());
+        self.heard.clear();
+        self.audio_receiver.try_iter().for_each(drop);
+        self.stream
+            .play()
+            .map_err(|err| Error::AudioInput(err.to_string()))?;
+        self.listening = true;
+        Ok(())
+    }
+
+    /// With barge-in, any speech interrupts the reply. Otherwise the start
+    /// of each stretch of speech is checked for the stop word.
+    fn interruption(&mut self, playback_level: f32) -> Result<Option<Interruption>> {
+        let Some(detector) = &mut self.listener else {
+            return Ok(None);
+        };
+        if !self.listening {
+            return Ok(None);
+        }
+        for chunk in self.audio_receiver.try_iter() {
+            if !self.heard.is_empty() {
+                self.heard.extend(chunk);
+            } else if detector.process(&chunk, playback_level) {
+                if self.barge_in {
+                    self.barged_in_audio = detector.take_audio();
+                    self.listening = false;
+                    return Ok(Some(Interruption::BargeIn));
+                }
+                self.heard = detector.take_audio();
+            }
+            if self.heard.len() >= STOP_WINDOW {
+                break;
+            }
+        }
+        if self.heard.len() < STOP_WINDOW {
+            return Ok(None);
+        }
 
-    // Initialise with a paused stream
-    stream.pause().expect("Failed to pause stream");
+        let heard = std::mem::take(&mut self.heard);
+        detector.start(self.vad.noise_floor());
+        // A Whisper pass, run from the reply's event loop, so let the runtime
+        // move its other tasks off this thread meanwhile
+        let text =
+            tokio::task::block_in_place(|| self.transcriber.spot(&heard, &self.stop_tokens))?;
+        if self.stop_word.find(&text).is_some() {
+            self.listening = false;
+            return Ok(Some(Interruption::Stop));
+        }
+        Ok(None)
+    }
 
-    stream
+    fn started_over_reply(&self) -> bool {
+        self.started_over_reply
+    }
 }
 
-impl GetInput for Stt {
-    /// Record until no voice activity is detected, then output the text.
-    fn record(&mut self) -> String {
-        // Start recording
-        println!("Start recording");
-        self.stream.play().expect("Failed to start recording");
+impl Transcriber {
+    pub fn new(config: &SttConfig) -> Result<Self> {
+        let path_to_model = config.model_path.clone().unwrap_or_default();
+        let mut ctx = WhisperContext::new(&path_to_model.to_string_lossy())?;
 
-        // Get the audio data from the input stream and run voice activity detection
-        self.run_voice_activity_detection();
+        let options = config.whisper.clone();
+        // whisper.cpp complains on stderr about codes it doesn't know, like "auto"
+        let language = match options.language.as_str() {
+            "auto" => None,
+            code => whisper_rs::get_lang_id(code).and_then(whisper_rs::get_lang_str),
+        };
+        let prompt_tokens = match &options.initial_prompt {
+            Some(prompt) => tokenize_prompt(&mut ctx, prompt)?,
+            None => vec![],
+        };
 
-        // Pause the stream
-        self.stream.pause().expect("Failed to pause stream");
-
-        // Not sure how we store this value somewhere in the struct
-        // without having to initialise it every time
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 0 });
-        params.set_n_threads(1);
-        params.set_translate(true);
-        params.set_language(Some("en"));
-        params.set_print_special(false);
-        params.set_print_progress(false);
-        params.set_print_realtime(false);
-        params.set_print_timestamps(false);
+        Ok(Self {
+            ctx,
+            options,
+            language,
+            prompt_tokens,
+        })
+    }
 
-        // Run the Whisper ASR model
-        println!("Run ASR model");
-        self.ctx
-            .full(params, &self.audio_data[..])
-            .expect("failed to run model");
+    /// Condition the next transcriptions on `prompt`, after the configured initial prompt.
+    pub fn set_prompt(&mut self, prompt: &str) -> Result<()> {
+        self.prompt_tokens = tokenize_prompt(&mut self.ctx, prompt)?;
+        Ok(())
+    }
 
-        // Clear the audio data
-        self.audio_data.clear();
+    /// Transcribe a file or stream of any sample rate and channel count.
+    pub fn transcribe_source(&mut self, source: &mut dyn AudioSource) -> Result<Transcript> {
+        let audio_data = audio_source::read_to_end(source)?;
+        self.transcribe(&audio_data)
+    }
 
-        // Fetch the results
-        let num_segments = self.ctx.full_n_segments();
+    /// Run Whisper on 16 kHz mono audio.
+    pub fn transcribe(&mut self, audio_data: &[f32]) -> Result<Transcript> {
+        let strategy = match self.options.beam_size {
+            Some(beam_size) => SamplingStrategy::BeamSearch {
+                beam_size: beam_size as i32,
+                patience: -1.0,
+            },
+            None => SamplingStrategy::Greedy { best_of: 1 },
+        };
+        let mut params = self.params(strategy);
+        params.set_tokens(&self.prompt_tokens);
 
-        (0..num_segments)
-            .map(|i| {
-                self.ctx
-                    .full_get_segment_text(i)
-                    .expect("failed to get segment")
-                    .trim()
-                    .to_string()
-            })
-            .filter(|segment| segment != "[BLANK_AUDIO]")
-            .collect::<Vec<String>>()
-            .join(" ")
+        run_whisper(&mut self.ctx, params, audio_data)
     }
-}
 
-impl Stt {
-    pub fn new(path_to_model: String) -> Self {
-        let ctx = WhisperContext::new(&path_to_model).expect("failed to load model");
+    /// A quick, rough transcription of a short clip, primed with `prompt` only.
+    fn spot(&mut self, audio_data: &[f32], prompt: &[WhisperToken]) -> Result<String> {
+        let mut params = self.params(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_tokens(prompt);
+        params.set_no_context(true);
+        params.set_single_segment(true);
+        params.set_max_tokens(SPOT_MAX_TOKENS);
+        params.set_audio_ctx(SPOT_AUDIO_CTX);
+
+        run_whisper(&mut self.ctx, params, audio_data).map(|transcript| transcript.text())
+    }
 
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 0 });
-        params.set_translate(true);
-        params.set_language(Some("en"));
+    // `full` takes the params by value, so they are rebuilt from the stored options
+    fn params(&self, strategy: SamplingStrategy) -> FullParams<'static, 'static> {
+        let mut params = FullParams::new(strategy);
+        params.set_n_threads(self.options.threads as i32);
+        params.set_translate(self.options.translate);
+        params.set_language(self.language);
+        params.set_temperature(self.options.temperature);
         params.set_print_special(false);
         params.set_print_progress(false);
         params.set_print_realtime(false);
         params.set_print_timestamps(false);
+        params
+    }
+}
+
+/// Tokenize text for Whisper to condition the transcription on.
+fn tokenize_prompt(ctx: &mut WhisperContext, prompt: &str) -> Result<Vec<WhisperToken>> {
+    // `tokenize` hands the pointer straight to C, so it must be NUL-terminated.
+    // There are never more tokens than bytes.
+    let mut tokens = ctx.tokenize(&format!("{}\0", prompt), prompt.len() + 1)?;
+
+    // whisper.cpp only looks at the last half of the text context
+    let max_tokens = ctx.n_text_ctx() as usize / 2;
+    if tokens.len() > max_tokens {
+        tokens.drain(..tokens.len() - max_tokens);
+    }
+    Ok(tokens)
+}
 
-        let (tx, audio_receiver) = mpsc::sync_channel(AUDIO_BUFFER);
+impl Stt {
+    /// `home_dir` is the project whose file names and symbols Whisper should recognize.
+    pub fn new(config: &SttConfig, home_dir: Option<PathBuf>) -> Result<Self> {
+        let mut transcriber = Transcriber::new(config)?;
+        let wake_tokens = tokenize_prompt(&mut transcriber.ctx, &config.wake_word)?;
+        let stop_tokens = tokenize_prompt(&mut transcriber.ctx, &config.stop_word)?;
+        let barge_in = config.barge_in.enabled && config.trigger == Trigger::Voice;
+        // Push-to-talk doesn't calibrate the VAD, so it can't tell speech apart
+        let listener = (config.trigger != Trigger::PushToTalk
+            && (barge_in || config.stop_word.contains(char::is_alphanumeric)))
+        .then(|| BargeInDetector::new(&config.vad, &config.barge_in));
+
+        let (tx, audio_receiver) = mpsc::sync_channel(CHANNEL_CAPACITY);
 
         // Create an audio stream
-        let stream = create_paused_audio_stream(tx);
+        let stream = audio_input::create_paused_audio_stream(config.input_device.as_deref(), tx)?;
 
-        Self {
-            ctx,
+        let mut stt = Self {
+            transcriber,
             audio_data: Vec::new(),
             audio_receiver,
             stream,
+            trigger: config.trigger,
+            streaming: config.streaming,
+            vad: VoiceActivityDetector::new(&config.vad),
+            max_utterance_len: (config.vad.max_utterance_secs * OUTPUT_SAMPLE_RATE as f32) as usize,
+            home_dir,
+            recent: vec![],
+            wake_word: Keyword::new(&config.wake_word),
+            wake_phrase: config.wake_word.clone(),
+            wake_tokens,
+            stop_word: Keyword::new(&config.stop_word),
+            stop_tokens,
+            listener,
+            barge_in,
+            listening: false,
+            heard: vec![],
+            barged_in_audio: vec![],
+            started_over_reply: false,
+        };
+        // Only voice activity detection needs to know the noise floor
+        if config.trigger != Trigger::PushToTalk {
+            stt.calibrate(Duration::from_millis(config.vad.calibration_ms as u64))?;
+        }
+
+        Ok(stt)
+    }
+
+    /// Prime Whisper with the project vocabulary and the end of the conversation.
+    ///
+    /// The project is scanned again every turn to pick up files written since.
+    fn update_prompt(&mut self) -> Result<()> {
+        let prompt = vocabulary::build_prompt(
+            self.transcriber.options.initial_prompt.as_deref(),
+            self.home_dir.as_deref(),
+            &self.recent,
+        );
+        self.transcriber.set_prompt(&prompt)
+    }
+
+    /// Measure the background noise so the VAD knows what silence sounds like.
+    fn calibrate(&mut self, duration: Duration) -> Result<()> {
+        println!("Calibrating, please stay quiet");
+        self.stream
+            .play()
+            .map_err(|err| Error::AudioInput(err.to_string()))?;
+
+        let mut background = vec![];
+        let deadline = Instant::now() + duration;
+        while let Some(chunk) = self.recv_until(deadline)? {
+            background.extend(chunk);
+        }
+
+        self.stream
+            .pause()
+            .map_err(|err| Error::AudioInput(err.to_string()))?;
+        self.vad.calibrate(&background);
+        println!("Noise floor: {:.4}", self.vad.noise_floor());
+        Ok(())
+    }
+
+    /// Record until no voice activity is detected, or `None` if discarded.
+    fn record_until_silence(&mut self) -> Result<Option<Transcript>> {
+        // Start recording
+        println!("Start recording");
+        self.stream
+            .play()
+            .map_err(|err| Error::AudioInput(err.to_string()))?;
+
+        // Get the audio data from the input stream and run voice activity detection.
+        // The cancel key only works if we are in a terminal.
+        let hotkeys = Hotkeys::enable().ok();
+        self.start_utterance();
+        let stop = self.receive_audio(None, hotkeys.as_ref())?;
+        drop(hotkeys);
+
+        // Pause the stream
+        self.stream
+            .pause()
+            .map_err(|err| Error::AudioInput(err.to_string()))?;
+        if stop == Stop::Cancelled {
+            return Ok(None);
+        }
+
+        let audio_data = std::mem::take(&mut self.audio_data);
+        println!("Run ASR model");
+        self.transcriber.transcribe(&audio_data).map(Some)
+    }
+
+    /// Wait for an utterance starting with the wake word and return what
+    /// follows it. If the wake word was said alone, the next utterance is
+    /// the request. `None` if discarded.
+    ///
+    /// Utterances are only transcribed in full once the wake word was heard,
+    /// so conversations in the room cost no more than a quick look at their
+    /// first `WAKE_WINDOW` samples.
+    fn record_after_wake_word(&mut self) -> Result<Option<Transcript>> {
+        println!("Say \"{}\" to start", self.wake_phrase);
+        self.stream
+            .play()
+            .map_err(|err| Error::AudioInput(err.to_string()))?;
+        let hotkeys = Hotkeys::enable().ok();
+        let transcript = self.listen_for_wake_word(hotkeys.as_ref());
+        drop(hotkeys);
+        self.stream
+            .pause()
+            .map_err(|err| Error::AudioInput(err.to_string()))?;
+        transcript
+    }
+
+    fn listen_for_wake_word(&mut self, hotkeys: Option<&Hotkeys>) -> Result<Option<Transcript>> {
+        self.start_utterance();
+        loop {
+            if self.receive_audio(None, hotkeys)? == Stop::Cancelled {
+                return Ok(None);
+            }
+            let head = &self.audio_data[..self.audio_data.len().min(WAKE_WINDOW)];
+            let heard = self.transcriber.spot(head, &self.wake_tokens)?;
+            if self.wake_word.find(&heard).is_some() {
+                break;
+            }
+            // Keep what arrived meanwhile, it may be the wake word
+            self.restart_utterance();
+        }
+
+        // Raw mode needs explicit carriage returns
+        print!("Run ASR model\r\n");
+        let audio_data = std::mem::take(&mut self.audio_data);
+        let mut transcript = self.transcriber.transcribe(&audio_data)?;
+        self.wake_word.strip(&mut transcript);
+        if !transcript.text().is_empty() {
+            return Ok(Some(transcript));
         }
+
+        print!("Listening\r\n");
+        self.restart_utterance();
+        if self.receive_audio(None, hotkeys)? == Stop::Cancelled {
+            return Ok(None);
+        }
+        let audio_data = std::mem::take(&mut self.audio_data);
+        print!("Run ASR model\r\n");
+        self.transcriber.transcribe(&audio_data).map(Some)
     }
 
-    pub fn record_streaming() -> String {
-        todo!("Not yet implemented")
+    /// Record while the talk key is held, or between two presses if the
+    /// terminal can't report key releases. `None` if discarded.
+    fn record_push_to_talk(&mut self) -> Result<Option<Transcript>> {
+        let hotkeys = Hotkeys::enable_with_release()?;
+        // Raw mode needs explicit carriage returns
+        if hotkeys.can_hold() {
+            print!("Hold Space to talk, Esc to discard\r\n");
+        } else {
+            print!("Press Space to start and again to stop talking, Esc to discard\r\n");
+        }
+        stdout().flush()?;
+        while hotkeys.wait()? != Hotkey::TalkPressed {}
+
+        self.start_utterance();
+        self.stream
+            .play()
+            .map_err(|err| Error::AudioInput(err.to_string()))?;
+        print!("Recording\r\n");
+        stdout().flush()?;
+
+        let started = Instant::now();
+        let stop = loop {
+            match hotkeys.poll()? {
+                Some(Hotkey::TalkReleased) => break Stop::Ended,
+                Some(Hotkey::TalkPressed)
+                    if !hotkeys.can_hold() && started.elapsed() > TOGGLE_DEBOUNCE =>
+                {
+                    break Stop::Ended
+                }
+                Some(Hotkey::Cancel) => break Stop::Cancelled,
+                _ => {}
+            }
+            if let Some(chunk) = self.recv_until(Instant::now() + KEY_POLL)? {
+                self.audio_data.extend(chunk);
+            }
+            if self.audio_data.len() >= self.max_utterance_len {
+                break Stop::Ended;
+            }
+        };
+
+        self.stream
+            .pause()
+            .map_err(|err| Error::AudioInput(err.to_string()))?;
+        drop(hotkeys);
+        if stop == Stop::Cancelled {
+            return Ok(None);
+        }
+
+        let audio_data = std::mem::take(&mut self.audio_data);
+        println!("Run ASR model");
+        self.transcriber.transcribe(&audio_data).map(Some)
     }
 
-    /// Simple voice activity detection using silence duration.
+    /// Record until no voice activity is detected, transcribing while the user speaks.
     ///
-    /// Note that this function will block the main thread,
-    /// while the audio data is being processed concurrently
-    /// through the audio input stream
-    fn run_voice_activity_detection(&mut self) {
-        let mut last_voice_activity = Instant::now();
-        while last_voice_activity.elapsed() < SILENCE_DURATION {
-            if let Ok(sample) = self.audio_receiver.try_recv() {
-                // Check for voice activity
-                if sample.abs() > VOLUME_THRESHOLD {
-                    last_voice_activity = Instant::now();
+    /// Every `STREAMING_STEP`, Whisper runs on a window over the end of the
+    /// recording and the hypothesis is shown on the current line. Once the
+    /// window is longer than `STREAMING_WINDOW`, all but its last segment are
+    /// final, and the window moves up to the start of that last segment. A
+    /// window that is one long segment is final as a whole, so each step
+    /// costs at most about `STREAMING_WINDOW` of audio.
+    fn record_streaming(&mut self) -> Result<Option<Transcript>> {
+        println!("Start recording");
+        self.stream
+            .play()
+            .map_err(|err| Error::AudioInput(err.to_string()))?;
+
+        let mut committed: Vec<Segment> = vec![];
+        let mut window_start = 0;
+        let hotkeys = Hotkeys::enable().ok();
+        self.start_utterance();
+
+        loop {
+            match self.receive_audio(Some(STREAMING_STEP), hotkeys.as_ref())? {
+                Stop::Timeout => {}
+                Stop::Ended => break,
+                Stop::Cancelled => {
+                    self.stream
+                        .pause()
+                        .map_err(|err| Error::AudioInput(err.to_string()))?;
+                    print!("\r\x1b[K");
+                    return Ok(None);
                 }
+            }
+            let window = &self.audio_data[window_start..];
+            if window.is_empty() {
+                // Nobody has said anything yet
+                continue;
+            }
+            let mut segments = self.transcriber.transcribe(window)?.segments;
 
-                // Add the sample to the audio_data buffer
-                self.audio_data.push(sample);
+            if window.len() > STREAMING_WINDOW {
+                // A lone segment is committed too, so the window can't keep growing
+                let last = if segments.len() > 1 { 1 } else { 0 };
+                let rest = segments.split_off(segments.len() - last);
+                let advance = rest
+                    .first()
+                    .map_or(window.len(), |segment| duration_to_samples(segment.start));
+                let offset = samples_to_duration(window_start);
+                committed.extend(segments.into_iter().map(|segment| shift(segment, offset)));
+                segments = rest;
+                window_start += advance;
             }
+
+            print_partial(&committed, &segments)?;
+        }
+        drop(hotkeys);
+
+        self.stream
+            .pause()
+            .map_err(|err| Error::AudioInput(err.to_string()))?;
+
+        // Finalize whatever is left in the window
+        let last = self
+            .transcriber
+            .transcribe(&self.audio_data[window_start..])?;
+        let offset = samples_to_duration(window_start);
+        // Committed segments were speech, so only a lone window can be all noise
+        let no_speech_prob = if committed.is_empty() {
+            last.no_speech_prob
+        } else {
+            0.0
+        };
+        committed.extend(
+            last.segments
+                .into_iter()
+                .map(|segment| shift(segment, offset)),
+        );
+        self.audio_data.clear();
+
+        // Clear the partial hypothesis
+        print!("\r\x1b[K");
+        stdout().flush()?;
+
+        Ok(Some(Transcript {
+            segments: committed,
+            no_speech_prob,
+        }))
+    }
+
+    /// Drop audio left over from the last turn, e.g. the end of our own speech,
+    /// unless the user talked over it, which starts the utterance.
+    fn start_utterance(&mut self) {
+        let barged_in_audio = std::mem::take(&mut self.barged_in_audio);
+        if barged_in_audio.is_empty() {
+            self.audio_receiver.try_iter().for_each(drop);
         }
+        self.restart_utterance();
+        self.started_over_reply = !barged_in_audio.is_empty();
+        self.vad.process(&barged_in_audio, &mut self.audio_data);
+    }
+
+    fn restart_utterance(&mut self) {
+        self.audio_data.clear();
+        self.vad.reset();
+        self.started_over_reply = false;
     }
+
+    /// Collect the utterance for up to `duration`, or until it ends if `None`.
+    ///
+    /// Note that this function will block the calling thread. It sleeps on
+    /// the channel between audio chunks, which the input stream sends
+    /// concurrently from the audio thread, waking up every `KEY_POLL` to
+    /// check for the cancel key if there are `hotkeys`.
+    fn receive_audio(
+        &mut self,
+        duration: Option<Duration>,
+        hotkeys: Option<&Hotkeys>,
+    ) -> Result<Stop> {
+        let deadline = duration.map(|duration| Instant::now() + duration);
+        loop {
+            if let Some(hotkeys) = hotkeys {
+                if hotkeys.poll()? == Some(Hotkey::Cancel) {
+                    return Ok(Stop::Cancelled);
+                }
+            }
+
+            let wake_up = hotkeys.map(|_| Instant::now() + KEY_POLL);
+            let chunk = match deadline.into_iter().chain(wake_up).min() {
+                Some(until) => self.recv_until(until)?,
+                None => Some(
+                    self.audio_receiver
+                        .recv()
+                        .map_err(|_| Error::AudioInput("input stream closed".into()))?,
+                ),
+            };
+            match chunk {
+                Some(chunk) if self.vad.process(&chunk, &mut self.audio_data) => {
+                    return Ok(Stop::Ended);
+                }
+                None if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                    return Ok(Stop::Timeout);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Block until the next chunk arrives, or return `None` at `deadline`.
+    fn recv_until(&self, deadline: Instant) -> Result<Option<Vec<f32>>> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.audio_receiver.recv_timeout(timeout) {
+            Ok(chunk) => Ok(Some(chunk)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(Error::AudioInput("input stream closed".into()))
+            }
+        }
+    }
+}
+
+fn run_whisper(
+    ctx: &mut WhisperContext,
+    params: FullParams,
+    audio_data: &[f32],
+) -> Result<Transcript> {
+    // Run the Whisper ASR model
+    ctx.full(params, audio_data)?;
+
+    // Fetch the results. Timestamps are in centiseconds.
+    let num_segments = ctx.full_n_segments();
+    let segments = (0..num_segments)
+        .map(|i| {
+            let tokens = (0..ctx.full_n_tokens(i))
+                // Special tokens all come after end-of-text
+                .filter(|&j| ctx.full_get_token_id(i, j) < ctx.token_eot())
+                .map(|j| {
+                    Ok(Token {
+                        text: ctx.full_get_token_text(i, j)?,
+                        prob: ctx.full_get_token_prob(i, j),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Segment {
+                text: ctx.full_get_segment_text(i)?.trim().to_string(),
+                start: centis_to_duration(ctx.full_get_segment_t0(i)),
+                end: centis_to_duration(ctx.full_get_segment_t1(i)),
+                tokens,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // This whisper.cpp doesn't expose its no-speech probability, so estimate it
+    // from how much of the audio Whisper annotated as e.g. "[BLANK_AUDIO]"
+    let total = segments
+        .iter()
+        .map(|segment| segment.end.saturating_sub(segment.start))
+        .sum::<Duration>();
+    let (non_speech, speech): (Vec<_>, Vec<_>) = segments
+        .into_iter()
+        .partition(|segment| segment.is_non_speech());
+    let no_speech_prob = if speech.is_empty() {
+        1.0
+    } else {
+        let silent = non_speech
+            .iter()
+            .map(|segment| segment.end.saturating_sub(segment.start))
+            .sum::<Duration>();
+        silent.as_secs_f32() / total.as_secs_f32().max(f32::EPSILON)
+    };
+
+    Ok(Transcript {
+        segments: speech,
+        no_speech_prob,
+    })
+}
+
+fn centis_to_duration(centis: i64) -> Duration {
+    Duration::from_millis(centis.max(0) as u64 * 10)
+}
+
+fn samples_to_duration(samples: usize) -> Duration {
+    Duration::from_secs_f64(samples as f64 / OUTPUT_SAMPLE_RATE as f64)
+}
+
+fn duration_to_samples(duration: Duration) -> usize {
+    (duration.as_secs_f64() * OUTPUT_SAMPLE_RATE as f64) as usize
+}
+
+/// Move a segment from a window's timeline to the recording's.
+fn shift(mut segment: Segment, offset: Duration) -> Segment {
+    segment.start += offset;
+    segment.end += offset;
+    segment
+}
+
+fn join_segments(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .map(|segment| segment.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Overwrite the current line with the end of the hypothesis so far.
+fn print_partial(committed: &[Segment], segments: &[Segment]) -> Result<()> {
+    let mut partial = join_segments(committed);
+    partial.push(' ');
+    partial.push_str(&join_segments(segments));
+
+    let chars = partial.trim().chars().collect::<Vec<_>>();
+    let tail = if chars.len() > PARTIAL_WIDTH {
+        format!(
+            "…{}",
+            chars[chars.len() - PARTIAL_WIDTH..]
+                .iter()
+                .collect::<String>()
+        )
+    } else {
+        chars.iter().collect()
+    };
+
+    let mut lock = stdout().lock();
+    write!(lock, "\r\x1b[K{}", tail)?;
+    lock.flush()?;
+    Ok(())
 }
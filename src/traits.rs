@@ -1,3 +1,60 @@
+use async_openai::types::ChatCompletionRequestMessage;
+
+use crate::chat_backend::TokenStream;
+use crate::error::Result;
+use crate::speech_engine::SpeechFuture;
+use crate::transcript::Transcript;
+
+/// How the user cut the reply short.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interruption {
+    /// Ctrl-C or the stop word
+    Stop,
+    /// Talking over the reply, which starts the next turn
+    BargeIn,
+}
+
 pub trait GetInput {
-    fn record(&mut self) -> String;
-}
\ No newline at end of file
+    fn record(&mut self) -> Result<Transcript>;
+
+    /// The latest turns of the conversation, for inputs that can use them
+    /// to recognize what is said next.
+    fn set_context(&mut self, _recent: &[String]) {}
+
+    /// Start listening for the user interrupting the reply, if the input can.
+    fn start_listening(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// How the user interrupted the reply since `start_listening`, if they
+    /// did, while it plays at `playback_level` RMS. Doesn't wait for audio.
+    /// After a barge-in, what they said so far begins the next `record`.
+    fn interruption(&mut self, _playback_level: f32) -> Result<Option<Interruption>> {
+        Ok(None)
+    }
+
+    /// Whether the last `record` began with what the user said over the reply.
+    fn started_over_reply(&self) -> bool {
+        false
+    }
+}
+
+pub trait ChatBackend {
+    /// Stream the content tokens of the reply to `messages`.
+    fn stream_chat(&self, messages: Vec<ChatCompletionRequestMessage>) -> TokenStream;
+}
+
+/// Turns the text of a reply into speech.
+pub trait SpeechEngine: Send + Sync {
+    /// Render `text` as audio rodio can decode, such as WAV or MP3.
+    /// No audio means there is nothing to play.
+    fn synthesize(&self, text: String) -> SpeechFuture;
+}
+
+/// Audio to transcribe, read in chunks.
+pub trait AudioSource {
+    fn sample_rate(&self) -> usize;
+    fn channels(&self) -> usize;
+    /// The next chunk of interleaved samples, or `None` at the end.
+    fn read(&mut self) -> Result<Option<Vec<f32>>>;
+}
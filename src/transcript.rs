@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+/// What the user said, with how sure the speech model is about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transcript {
+    pub segments: Vec<Segment>,
+    /// How likely it is that there was no speech at all
+    pub no_speech_prob: f32,
+}
+
+/// A stretch of speech Whisper decoded in one go.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub text: String,
+    /// From the start of the recording
+    pub start: Duration,
+    pub end: Duration,
+    /// Text tokens only, without Whisper's special and timestamp tokens
+    pub tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub prob: f32,
+}
+
+/// What the chat loop should do with a transcript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    /// Show the text and let the user confirm it
+    Confirm,
+    /// Nothing usable was heard
+    Repeat,
+}
+
+impl Transcript {
+    /// Text typed by the user, which needs no checking.
+    pub fn typed(text: String) -> Self {
+        Self {
+            segments: vec![Segment {
+                text,
+                start: Duration::ZERO,
+                end: Duration::ZERO,
+                tokens: vec![],
+            }],
+            no_speech_prob: 0.0,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.segments
+            .iter()
+            .map(|segment| segment.text.trim())
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Mean probability of the text tokens, 1 if there are none to doubt.
+    pub fn confidence(&self) -> f32 {
+        let probs = self
+            .segments
+            .iter()
+            .flat_map(|segment| segment.tokens.iter().map(|token| token.prob))
+            .collect::<Vec<_>>();
+        if probs.is_empty() {
+            return 1.0;
+        }
+        probs.iter().sum::<f32>() / probs.len() as f32
+    }
+
+    pub fn verdict(&self, min_confidence: f32, max_no_speech_prob: f32) -> Verdict {
+        if self.text().is_empty() || self.no_speech_prob > max_no_speech_prob {
+            Verdict::Repeat
+        } else if self.confidence() < min_confidence {
+            Verdict::Confirm
+        } else {
+            Verdict::Accept
+        }
+    }
+}
+
+impl Segment {
+    /// Whisper writes non-speech like "[BLANK_AUDIO]" or "(music)" in brackets.
+    pub fn is_non_speech(&self) -> bool {
+        let text = self.text.trim();
+        (text.starts_with('[') && text.ends_with(']'))
+            || (text.starts_with('(') && text.ends_with(')'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(text: &str, probs: &[f32]) -> Segment {
+        Segment {
+            text: text.to_string(),
+            start: Duration::ZERO,
+            end: Duration::from_secs(1),
+            tokens: probs
+                .iter()
+                .map(|&prob| Token {
+                    text: "x".to_string(),
+                    prob,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_verdict() {
+        let clear = Transcript {
+            segments: vec![segment("open main.rs", &[0.9, 0.8])],
+            no_speech_prob: 0.0,
+        };
+        assert_eq!(clear.verdict(0.5, 0.6), Verdict::Accept);
+
+        let mumbled = Transcript {
+            segments: vec![segment("oh pen mane", &[0.3, 0.2, 0.4])],
+            no_speech_prob: 0.0,
+        };
+        assert_eq!(mumbled.verdict(0.5, 0.6), Verdict::Confirm);
+
+        let silence = Transcript {
+            segments: vec![],
+            no_speech_prob: 1.0,
+        };
+        assert_eq!(silence.verdict(0.5, 0.6), Verdict::Repeat);
+
+        assert_eq!(
+            Transcript::typed("hi".into()).verdict(0.5, 0.6),
+            Verdict::Accept
+        );
+    }
+}
@@ -1,11 +1,20 @@
+use crate::error::{Error, Result};
 use crate::traits::GetInput;
+use crate::transcript::Transcript;
 
-struct TtyInput;
+pub struct TtyInput;
 
 impl GetInput for TtyInput {
-    fn record(&mut self) -> String {
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).unwrap();
-        input.trim().to_string()
+    /// Read the next line that isn't blank.
+    fn record(&mut self) -> Result<Transcript> {
+        loop {
+            let mut input = String::new();
+            if std::io::stdin().read_line(&mut input)? == 0 {
+                return Err(Error::EndOfInput);
+            }
+            if !input.trim().is_empty() {
+                return Ok(Transcript::typed(input.trim().to_string()));
+            }
+        }
     }
 }
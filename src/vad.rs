@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+
+use crate::config::VadConfig;
+
+const SAMPLE_RATE: usize = 16_000;
+
+// Frames crossing zero more often than this look like broadband noise, not voice
+const MAX_SPEECH_ZCR: f32 = 0.35;
+// Frames this far above the speech threshold count as speech whatever their ZCR
+const STRONG_SPEECH_RATIO: f32 = 2.0;
+// How quickly the noise floor follows the background during silence
+const NOISE_FLOOR_ADAPTATION: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// No speech yet, only the pre-roll is kept
+    Waiting,
+    Speech,
+    Ended,
+}
+
+/// Frame-based voice activity detection on 16 kHz mono audio.
+///
+/// Each frame is classified by its RMS energy relative to the noise floor
+/// and its zero-crossing rate. The noise floor is measured by `calibrate`
+/// and then follows the background while nobody is speaking.
+///
+/// The last `pre_roll_ms` before speech is kept so the first syllable isn't
+/// clipped, and the utterance ends after `hangover_ms` without speech or
+/// after `max_utterance_secs`.
+pub struct VoiceActivityDetector {
+    frame_len: usize,
+    hangover_frames: usize,
+    pre_roll_len: usize,
+    max_utterance_len: usize,
+    threshold_ratio: f32,
+    min_energy: f32,
+    noise_floor: f32,
+
+    state: State,
+    frame: Vec<f32>,
+    pre_roll: VecDeque<f32>,
+    silent_frames: usize,
+    utterance_len: usize,
+}
+
+impl VoiceActivityDetector {
+    pub fn new(config: &VadConfig) -> Self {
+        let frame_len = config.frame_ms * SAMPLE_RATE / 1000;
+        Self {
+            frame_len,
+            hangover_frames: config.hangover_ms.div_ceil(config.frame_ms),
+            pre_roll_len: config.pre_roll_ms * SAMPLE_RATE / 1000,
+            max_utterance_len: (config.max_utterance_secs * SAMPLE_RATE as f32) as usize,
+            threshold_ratio: config.threshold_ratio,
+            min_energy: config.min_energy,
+            noise_floor: config.min_energy,
+            state: State::Waiting,
+            frame: Vec::with_capacity(frame_len),
+            pre_roll: VecDeque::new(),
+            silent_frames: 0,
+            utterance_len: 0,
+        }
+    }
+
+    /// Set the noise floor from audio of the room with nobody speaking.
+    pub fn calibrate(&mut self, background: &[f32]) {
+        let energies = background
+            .chunks_exact(self.frame_len)
+            .map(rms)
+            .collect::<Vec<_>>();
+        if !energies.is_empty() {
+            self.noise_floor = energies.iter().sum::<f32>() / energies.len() as f32;
+        }
+    }
+
+    pub fn noise_floor(&self) -> f32 {
+        self.noise_floor
+    }
+
+    /// Get ready for the next utterance.
+    pub fn reset(&mut self) {
+        self.state = State::Waiting;
+        self.frame.clear();
+        self.pre_roll.clear();
+        self.silent_frames = 0;
+        self.utterance_len = 0;
+    }
+
+    /// Feed audio, appending the utterance (including its pre-roll) to `utterance`.
+    ///
+    /// Returns `true` once the utterance has ended. Samples after the end are dropped.
+    pub fn process(&mut self, samples: &[f32], utterance: &mut Vec<f32>) -> bool {
+        for &sample in samples {
+            if self.state == State::Ended {
+                break;
+            }
+            self.frame.push(sample);
+            if self.frame.len() == self.frame_len {
+                let frame = std::mem::replace(&mut self.frame, Vec::with_capacity(self.frame_len));
+                self.process_frame(&frame, utterance);
+            }
+        }
+        self.state == State::Ended
+    }
+
+    fn process_frame(&mut self, frame: &[f32], utterance: &mut Vec<f32>) {
+        let is_speech = self.is_speech(frame);
+
+        match self.state {
+            State::Waiting if is_speech => {
+                self.state = State::Speech;
+                self.utterance_len = self.pre_roll.len() + frame.len();
+                utterance.extend(self.pre_roll.drain(..));
+                utterance.extend_from_slice(frame);
+            }
+            State::Waiting => {
+                self.adapt_noise_floor(frame);
+                self.pre_roll.extend(frame);
+                while self.pre_roll.len() > self.pre_roll_len {
+                    self.pre_roll.pop_front();
+                }
+            }
+            State::Speech => {
+                utterance.extend_from_slice(frame);
+                self.utterance_len += frame.len();
+                self.silent_frames = if is_speech { 0 } else { self.silent_frames + 1 };
+
+                if self.silent_frames >= self.hangover_frames
+                    || self.utterance_len >= self.max_utterance_len
+                {
+                    self.state = State::Ended;
+                }
+            }
+            State::Ended => {}
+        }
+    }
+
+    fn is_speech(&self, frame: &[f32]) -> bool {
+        let energy = rms(frame);
+        let threshold = (self.noise_floor * self.threshold_ratio).max(self.min_energy);
+        if energy > threshold * STRONG_SPEECH_RATIO {
+            return true;
+        }
+        energy > threshold && zero_crossing_rate(frame) < MAX_SPEECH_ZCR
+    }
+
+    fn adapt_noise_floor(&mut self, frame: &[f32]) {
+        self.noise_floor += NOISE_FLOOR_ADAPTATION * (rms(frame) - self.noise_floor);
+    }
+}
+
+pub fn rms(frame: &[f32]) -> f32 {
+    (frame.iter().map(|sample| sample * sample).sum::<f32>() / frame.len() as f32).sqrt()
+}
+
+fn zero_crossing_rate(frame: &[f32]) -> f32 {
+    let crossings = frame
+        .windows(2)
+        .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+        .count();
+    crossings as f32 / frame.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(secs: f32, amplitude: f32) -> Vec<f32> {
+        let len = (secs * SAMPLE_RATE as f32) as usize;
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                amplitude * (2.0 * std::f32::consts::PI * 220.0 * t).sin()
+            })
+            .collect()
+    }
+
+    fn silence(secs: f32) -> Vec<f32> {
+        vec![0.0; (secs * SAMPLE_RATE as f32) as usize]
+    }
+
+    #[test]
+    fn test_utterance_keeps_pre_roll_and_ends_after_hangover() {
+        let config = VadConfig::default();
+        let mut vad = VoiceActivityDetector::new(&config);
+        vad.calibrate(&silence(0.5));
+
+        let mut utterance = vec![];
+        assert!(!vad.process(&silence(1.0), &mut utterance));
+        assert!(utterance.is_empty());
+
+        assert!(!vad.process(&tone(1.0, 0.3), &mut utterance));
+        assert!(vad.process(&silence(2.0), &mut utterance));
+
+        let pre_roll = config.pre_roll_ms * SAMPLE_RATE / 1000;
+        let hangover = config.hangover_ms * SAMPLE_RATE / 1000;
+        assert!(utterance.len() >= pre_roll + SAMPLE_RATE + hangover);
+        assert!(utterance.len() < pre_roll + SAMPLE_RATE + hangover + 2 * vad.frame_len);
+    }
+
+    #[test]
+    fn test_noise_below_threshold_is_not_speech() {
+        let mut vad = VoiceActivityDetector::new(&VadConfig::default());
+        vad.calibrate(&tone(0.5, 0.02));
+
+        let mut utterance = vec![];
+        assert!(!vad.process(&tone(2.0, 0.03), &mut utterance));
+        assert!(utterance.is_empty());
+    }
+
+    #[test]
+    fn test_max_utterance_length() {
+        let config = VadConfig {
+            max_utterance_secs: 1.0,
+            ..VadConfig::default()
+        };
+        let mut vad = VoiceActivityDetector::new(&config);
+
+        let mut utterance = vec![];
+        assert!(vad.process(&tone(3.0, 0.3), &mut utterance));
+        assert!(utterance.len() <= SAMPLE_RATE + vad.frame_len);
+    }
+}
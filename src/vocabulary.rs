@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+// Whisper only conditions on the last 224 tokens, roughly this many characters
+const MAX_PROMPT_CHARS: usize = 800;
+// Share of the prompt kept for the conversation, the rest is vocabulary
+const MAX_HISTORY_CHARS: usize = 300;
+const MAX_FILES: usize = 200;
+const MAX_FILE_BYTES: u64 = 256 * 1024;
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules", "__pycache__"];
+const DECLARATION_KEYWORDS: &[&str] = &[
+    "fn",
+    "struct",
+    "enum",
+    "trait",
+    "impl",
+    "mod",
+    "type",
+    "const",
+    "def",
+    "class",
+    "function",
+    "interface",
+];
+
+/// Build the text Whisper is primed with before each utterance.
+///
+/// Whisper tends to spell words the way they appear in its prompt, so the
+/// prompt lists the file names and symbols of the project followed by the
+/// end of the conversation, which is closest to what is about to be said.
+pub fn build_prompt(
+    initial_prompt: Option<&str>,
+    home_dir: Option<&Path>,
+    recent: &[String],
+) -> String {
+    let recent = recent.join(" ");
+    let history = tail(&recent, MAX_HISTORY_CHARS);
+
+    let mut prompt = initial_prompt.unwrap_or_default().trim().to_string();
+    let budget = MAX_PROMPT_CHARS.saturating_sub(prompt.len() + history.len());
+    let vocabulary = home_dir.map(project_vocabulary).unwrap_or_default();
+
+    let mut glossary = String::new();
+    for word in vocabulary {
+        if glossary.len() + word.len() + 2 > budget {
+            break;
+        }
+        if !glossary.is_empty() {
+            glossary.push_str(", ");
+        }
+        glossary.push_str(&word);
+    }
+
+    for part in [glossary, history.to_string()] {
+        if part.is_empty() {
+            continue;
+        }
+        if !prompt.is_empty() {
+            prompt.push_str(". ");
+        }
+        prompt.push_str(&part);
+    }
+    prompt
+}
+
+/// File names and code symbols under `home_dir`, most distinctive first.
+pub fn project_vocabulary(home_dir: &Path) -> Vec<String> {
+    let mut files = vec![];
+    collect_files(home_dir, &mut files);
+
+    let mut seen = HashSet::new();
+    let mut file_names = vec![];
+    let mut symbols = vec![];
+    for path in files {
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            if seen.insert(name.to_string()) {
+                file_names.push(name.to_string());
+            }
+        }
+
+        if std::fs::metadata(&path).map_or(true, |meta| meta.len() > MAX_FILE_BYTES) {
+            continue;
+        }
+        // Binary files fail to read as UTF-8 and are skipped
+        let Ok(source) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        for symbol in code_symbols(&source) {
+            if seen.insert(symbol.to_string()) {
+                symbols.push(symbol.to_string());
+            }
+        }
+    }
+
+    file_names.extend(symbols);
+    file_names
+}
+
+/// Walk `dir` breadth-first, skipping hidden and build directories.
+fn collect_files(dir: &Path, files: &mut Vec<std::path::PathBuf>) {
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut entries = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        for path in entries {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            if name.starts_with('.') || SKIPPED_DIRS.contains(&name) {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.insert(0, path);
+            } else if files.len() < MAX_FILES {
+                files.push(path);
+            } else {
+                return;
+            }
+        }
+    }
+}
+
+/// Identifiers a speech model would not spell on its own:
+/// declared names and anything in snake_case or camelCase.
+fn code_symbols(source: &str) -> Vec<&str> {
+    let words = source
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut symbols = vec![];
+    for (i, word) in words.iter().enumerate() {
+        if word.len() < 3 || word.starts_with(|c: char| c.is_ascii_digit()) {
+            continue;
+        }
+        let declared = i > 0 && DECLARATION_KEYWORDS.contains(&words[i - 1]);
+        let snake_case = word.trim_matches('_').contains('_');
+        let camel_case = word
+            .chars()
+            .zip(word.chars().skip(1))
+            .any(|(a, b)| a.is_lowercase() && b.is_uppercase());
+        if declared || snake_case || camel_case {
+            symbols.push(*word);
+        }
+    }
+    symbols
+}
+
+/// The last `max_chars` characters of `text`, starting at a word boundary.
+fn tail(text: &str, max_chars: usize) -> &str {
+    let text = text.trim();
+    if text.len() <= max_chars {
+        return text;
+    }
+    let mut start = text.len() - max_chars;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    let tail = &text[start..];
+    tail.split_once(' ').map_or(tail, |(_, rest)| rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_symbols() {
+        let source = "fn main() {\n    let audio_data = readWav(path);\n    struct Stt;\n}";
+        assert_eq!(
+            code_symbols(source),
+            vec!["main", "audio_data", "readWav", "Stt"]
+        );
+    }
+
+    #[test]
+    fn test_project_vocabulary() {
+        let dir = std::env::temp_dir().join(format!("jarvy-vocabulary-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join(".jarvy")).unwrap();
+        std::fs::write(dir.join("src/audio_source.rs"), "pub struct MonoResampler;").unwrap();
+        std::fs::write(dir.join(".jarvy/session.json"), "{\"hidden_key\": 1}").unwrap();
+
+        let vocabulary = project_vocabulary(&dir);
+        assert_eq!(vocabulary, vec!["audio_source.rs", "MonoResampler"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_prompt_keeps_end_of_conversation() {
+        let recent = vec!["word ".repeat(200), "the last thing said".to_string()];
+        let prompt = build_prompt(Some("Jarvy"), None, &recent);
+
+        assert!(prompt.starts_with("Jarvy. "));
+        assert!(prompt.ends_with("the last thing said"));
+        assert!(prompt.len() <= MAX_PROMPT_CHARS);
+    }
+}